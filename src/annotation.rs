@@ -0,0 +1,366 @@
+use image::{Rgba, RgbaImage};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    font,
+    raster::{self, Mode, Point},
+};
+
+/// The highlighter is a broad marker, its stroke is this many times the style's width.
+const HIGHLIGHT_WIDTH: f64 = 4.0;
+/// Opacity of the highlighter, on top of the alpha of its colour.
+const HIGHLIGHT_OPACITY: f64 = 0.5;
+/// Radius of a step marker relative to the font size of its number.
+const MARKER_RADIUS: f64 = 0.8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Style {
+    #[serde(with = "crate::color::hex")]
+    pub color: Rgba<u8>,
+    /// Stroke width in image pixels.
+    pub width: f64,
+    /// Text height in image pixels.
+    pub font_size: f64,
+}
+
+impl Default for Style {
+    fn default() -> Self {
+        Self {
+            color: crate::color::PALETTE[0],
+            width: 4.0,
+            font_size: 24.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case", tag = "type")]
+pub enum Shape {
+    Rectangle {
+        from: Point,
+        to: Point,
+    },
+    Arrow {
+        from: Point,
+        to: Point,
+    },
+    Line {
+        from: Point,
+        to: Point,
+    },
+    /// Single line of text starting at the baseline position `at`.
+    Text {
+        at: Point,
+        text: String,
+    },
+    /// Line with end ticks, labelled with its length.
+    Dimension {
+        from: Point,
+        to: Point,
+    },
+    /// Freehand stroke through the mouse positions, smoothed when it is drawn.
+    Freehand {
+        points: Vec<Point>,
+    },
+    /// Like `Freehand`, but broad and multiplied with the image.
+    Highlight {
+        points: Vec<Point>,
+    },
+    /// Numbered circle centered at `at`, for step by step instructions.
+    Marker {
+        at: Point,
+        /// Position in the sequence of markers, assigned by `History::annotations`.
+        number: u32,
+    },
+}
+
+/// A vector shape drawn on top of the image, positions are in image pixel coordinates.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Annotation {
+    pub shape: Shape,
+    pub style: Style,
+}
+
+/// Line segments and filled polygons that make up an annotation.
+#[derive(Debug, Default)]
+pub struct Outline {
+    pub lines: Vec<[Point; 2]>,
+    pub polygons: Vec<Vec<Point>>,
+}
+
+impl Annotation {
+    pub fn new(shape: Shape, style: Style) -> Self {
+        Self { shape, style }
+    }
+
+    /// Moves the annotation by `offset`, e.g. to map it between the source and cropped image.
+    pub fn translate(&self, [dx, dy]: Point) -> Self {
+        let t = |p: &Point| [p[0] + dx, p[1] + dy];
+
+        let shape = match &self.shape {
+            Shape::Rectangle { from, to } => Shape::Rectangle {
+                from: t(from),
+                to: t(to),
+            },
+            Shape::Arrow { from, to } => Shape::Arrow {
+                from: t(from),
+                to: t(to),
+            },
+            Shape::Line { from, to } => Shape::Line {
+                from: t(from),
+                to: t(to),
+            },
+            Shape::Text { at, text } => Shape::Text {
+                at: t(at),
+                text: text.clone(),
+            },
+            Shape::Dimension { from, to } => Shape::Dimension {
+                from: t(from),
+                to: t(to),
+            },
+            Shape::Freehand { points } => Shape::Freehand {
+                points: points.iter().map(t).collect(),
+            },
+            Shape::Highlight { points } => Shape::Highlight {
+                points: points.iter().map(t).collect(),
+            },
+            Shape::Marker { at, number } => Shape::Marker {
+                at: t(at),
+                number: *number,
+            },
+        };
+
+        Self::new(shape, self.style)
+    }
+
+    /// Stroke width of lines in image pixels.
+    pub fn width(&self) -> f64 {
+        match self.shape {
+            Shape::Highlight { .. } => self.style.width * HIGHLIGHT_WIDTH,
+            _ => self.style.width,
+        }
+    }
+
+    pub fn color(&self) -> Rgba<u8> {
+        let mut color = self.style.color;
+
+        if let Shape::Highlight { .. } = self.shape {
+            color[3] = (color[3] as f64 * HIGHLIGHT_OPACITY).round() as u8;
+        }
+
+        color
+    }
+
+    /// Text is drawn in the annotation's colour, except on top of a marker.
+    pub fn text_color(&self) -> Rgba<u8> {
+        match self.shape {
+            Shape::Marker { .. } => crate::color::contrasting(self.style.color),
+            _ => self.color(),
+        }
+    }
+
+    /// Radius of the circle of a step marker.
+    pub fn marker_radius(&self) -> f64 {
+        self.style.font_size * MARKER_RADIUS
+    }
+
+    /// Whether `p` is on top of this annotation, if it is a step marker.
+    pub fn marker_contains(&self, p: Point) -> bool {
+        match self.shape {
+            Shape::Marker { at, .. } => raster::distance(at, p) <= self.marker_radius(),
+            _ => false,
+        }
+    }
+
+    pub fn mode(&self) -> Mode {
+        match self.shape {
+            Shape::Highlight { .. } => Mode::Multiply,
+            _ => Mode::Over,
+        }
+    }
+
+    /// Text drawn with the annotation and its baseline position.
+    pub fn text(&self) -> Option<(Point, String)> {
+        match &self.shape {
+            Shape::Text { at, text } => Some((*at, text.clone())),
+            Shape::Dimension { from, to } => {
+                let middle = [(from[0] + to[0]) / 2.0, (from[1] + to[1]) / 2.0];
+                let at = [middle[0], middle[1] - self.style.width - 4.0];
+
+                Some((at, format!("{:.0} px", raster::distance(*from, *to))))
+            }
+            Shape::Marker { at, number } => {
+                let text = number.to_string();
+                let size = self.style.font_size;
+
+                // centered on the marker, digits are about 0.73 em high
+                let at = [
+                    at[0] - font::width(&text, size) / 2.0,
+                    at[1] + size * 0.73 / 2.0,
+                ];
+
+                Some((at, text))
+            }
+            _ => None,
+        }
+    }
+
+    /// Text has no outline, it is drawn from the glyphs of the font.
+    pub fn outline(&self) -> Outline {
+        match &self.shape {
+            &Shape::Rectangle { from: a, to: c } => {
+                let (b, d) = ([c[0], a[1]], [a[0], c[1]]);
+
+                Outline {
+                    lines: vec![[a, b], [b, c], [c, d], [d, a]],
+                    ..Default::default()
+                }
+            }
+            &Shape::Line { from, to } => Outline {
+                lines: vec![[from, to]],
+                ..Default::default()
+            },
+            &Shape::Arrow { from, to } => {
+                let head = arrow_head(from, to, self.style.width);
+
+                // stop the shaft at the base of the head so it does not poke out of the tip
+                let base = [
+                    (head[1][0] + head[2][0]) / 2.0,
+                    (head[1][1] + head[2][1]) / 2.0,
+                ];
+
+                Outline {
+                    lines: vec![[from, base]],
+                    polygons: vec![head.to_vec()],
+                }
+            }
+            &Shape::Dimension { from, to } => {
+                let length = f64::max(self.style.width * 3.0, 12.0) / 2.0;
+
+                let (dx, dy) = (to[0] - from[0], to[1] - from[1]);
+                let len = f64::max((dx * dx + dy * dy).sqrt(), f64::EPSILON);
+                let (nx, ny) = (-dy / len * length, dx / len * length);
+
+                let tick = |p: Point| [[p[0] - nx, p[1] - ny], [p[0] + nx, p[1] + ny]];
+
+                Outline {
+                    lines: vec![[from, to], tick(from), tick(to)],
+                    ..Default::default()
+                }
+            }
+            Shape::Freehand { points } | Shape::Highlight { points } => {
+                let points = smooth(points);
+
+                // a single click leaves a dot
+                let lines = match points.as_slice() {
+                    [point] => vec![[*point, *point]],
+                    points => points.windows(2).map(|w| [w[0], w[1]]).collect(),
+                };
+
+                Outline {
+                    lines,
+                    ..Default::default()
+                }
+            }
+            &Shape::Marker { at, .. } => {
+                const SEGMENTS: usize = 48;
+                let radius = self.marker_radius();
+
+                let circle = (0..SEGMENTS)
+                    .map(|i| {
+                        let angle = i as f64 / SEGMENTS as f64 * std::f64::consts::TAU;
+                        [at[0] + radius * angle.cos(), at[1] + radius * angle.sin()]
+                    })
+                    .collect();
+
+                Outline {
+                    polygons: vec![circle],
+                    ..Default::default()
+                }
+            }
+            Shape::Text { .. } => Outline::default(),
+        }
+    }
+
+    /// Draws the annotation into `image`.
+    pub fn rasterize(&self, image: &mut RgbaImage) {
+        let Outline { lines, polygons } = self.outline();
+        let width = self.width();
+
+        let points = lines
+            .iter()
+            .flatten()
+            .chain(polygons.iter().flatten())
+            .copied()
+            .collect::<Vec<_>>();
+
+        raster::paint(
+            image,
+            raster::bounds(&points, width / 2.0 + 1.0),
+            self.color(),
+            self.mode(),
+            |p| {
+                let lines = lines
+                    .iter()
+                    .map(|[a, b]| raster::stroke(raster::segment_distance(p, *a, *b), width));
+                let polygons = polygons
+                    .iter()
+                    .map(|points| raster::fill(raster::polygon_distance(p, points)));
+
+                lines.chain(polygons).fold(0.0, f64::max)
+            },
+        );
+
+        // after the outline, the number of a marker is drawn on top of it
+        if let Some((at, text)) = self.text() {
+            let color = self.text_color();
+
+            font::layout(&text, self.style.font_size, at, |x, y, v| {
+                if x >= 0 && y >= 0 {
+                    raster::blend(image, x as u32, y as u32, color, v as f64);
+                }
+            });
+        }
+    }
+}
+
+/// Rounds off the corners of a polyline by cutting each of them twice, the ends stay in place.
+fn smooth(points: &[Point]) -> Vec<Point> {
+    let lerp = |a: Point, b: Point, t: f64| [a[0] + (b[0] - a[0]) * t, a[1] + (b[1] - a[1]) * t];
+
+    (0..2).fold(points.to_vec(), |points, _| {
+        if points.len() < 3 {
+            return points;
+        }
+
+        let mut smooth = vec![points[0]];
+
+        for w in points.windows(2) {
+            smooth.push(lerp(w[0], w[1], 0.25));
+            smooth.push(lerp(w[0], w[1], 0.75));
+        }
+
+        smooth.push(points[points.len() - 1]);
+        smooth
+    })
+}
+
+/// Triangle at `to` pointing away from `from`: tip, left and right corner.
+fn arrow_head(from: Point, to: Point, width: f64) -> [Point; 3] {
+    let length = f64::max(width * 4.0, 12.0);
+
+    let (dx, dy) = (to[0] - from[0], to[1] - from[1]);
+    let len = f64::max((dx * dx + dy * dy).sqrt(), f64::EPSILON);
+    let (ux, uy) = (dx / len, dy / len);
+
+    // never make the head longer than the arrow itself
+    let length = f64::min(length, len);
+    let base = [to[0] - ux * length, to[1] - uy * length];
+    let half = length / 2.0;
+
+    [
+        to,
+        [base[0] - uy * half, base[1] + ux * half],
+        [base[0] + uy * half, base[1] - ux * half],
+    ]
+}
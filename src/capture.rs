@@ -0,0 +1,310 @@
+use std::{fmt, thread, time::Duration};
+
+use image::{Rgba, RgbaImage};
+use log::info;
+use x11rb::{
+    connection::Connection,
+    errors::{ConnectError, ConnectionError, ReplyError},
+    protocol::{
+        randr,
+        xproto::{self, ImageFormat, ImageOrder},
+    },
+};
+
+/// Screenshot of the X11 root window, optionally limited to a single monitor.
+#[derive(Debug, Clone, Default)]
+pub struct Capture {
+    /// Index of the monitor as listed by `xrandr --listmonitors`; `None` captures all of them.
+    pub monitor: Option<usize>,
+    pub delay: Duration,
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Connect(ConnectError),
+    Reply(ReplyError),
+    NoSuchMonitor(usize),
+    UnsupportedVisual(u8),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Connect(e) => write!(f, "failed to connect to X server: {}", e),
+            Error::Reply(e) => write!(f, "X request failed: {}", e),
+            Error::NoSuchMonitor(n) => write!(f, "there is no monitor {}", n),
+            Error::UnsupportedVisual(depth) => {
+                write!(
+                    f,
+                    "can not read pixels of the root window with depth {}",
+                    depth
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<ConnectError> for Error {
+    fn from(e: ConnectError) -> Self {
+        Error::Connect(e)
+    }
+}
+
+impl From<ConnectionError> for Error {
+    fn from(e: ConnectionError) -> Self {
+        Error::Reply(e.into())
+    }
+}
+
+impl From<ReplyError> for Error {
+    fn from(e: ReplyError) -> Self {
+        Error::Reply(e)
+    }
+}
+
+impl Capture {
+    /// Connects to the display in `$DISPLAY` and reads the pixels of the root window.
+    pub fn capture(&self) -> Result<RgbaImage, Error> {
+        if self.delay > Duration::from_secs(0) {
+            info!("capturing screen in {:?}..", self.delay);
+            thread::sleep(self.delay);
+        }
+
+        let (conn, screen_num) = x11rb::connect(None)?;
+        let setup = conn.setup();
+        let screen = &setup.roots[screen_num];
+
+        let (x, y, width, height) = match self.monitor {
+            Some(n) => {
+                let monitors = randr::get_monitors(&conn, screen.root, true)?
+                    .reply()?
+                    .monitors;
+                let monitor = monitors.get(n).ok_or(Error::NoSuchMonitor(n))?;
+
+                (monitor.x, monitor.y, monitor.width, monitor.height)
+            }
+            None => (0, 0, screen.width_in_pixels, screen.height_in_pixels),
+        };
+
+        info!("capturing {}x{}+{}+{}..", width, height, x, y);
+
+        let reply = xproto::get_image(
+            &conn,
+            ImageFormat::Z_PIXMAP,
+            screen.root,
+            x,
+            y,
+            width,
+            height,
+            !0,
+        )?
+        .reply()?;
+
+        let visual = screen
+            .allowed_depths
+            .iter()
+            .flat_map(|depth| depth.visuals.iter())
+            .find(|visual| visual.visual_id == reply.visual)
+            .ok_or(Error::UnsupportedVisual(reply.depth))?;
+
+        let format = setup
+            .pixmap_formats
+            .iter()
+            .find(|format| format.depth == reply.depth)
+            .filter(|format| format.bits_per_pixel % 8 == 0)
+            .ok_or(Error::UnsupportedVisual(reply.depth))?;
+
+        let pixels = PixelLayout {
+            bytes_per_pixel: format.bits_per_pixel as usize / 8,
+            stride: pad(
+                width as usize * format.bits_per_pixel as usize,
+                format.scanline_pad as usize,
+            ) / 8,
+            msb_first: setup.image_byte_order == ImageOrder::MSB_FIRST,
+            masks: [visual.red_mask, visual.green_mask, visual.blue_mask],
+        };
+
+        Ok(pixels.decode(&reply.data, width as u32, height as u32))
+    }
+}
+
+/// Rounds `bits` up to a multiple of `pad`.
+fn pad(bits: usize, pad: usize) -> usize {
+    bits.div_ceil(pad) * pad
+}
+
+/// Memory layout of a `ZPixmap` image as sent by the X server.
+struct PixelLayout {
+    bytes_per_pixel: usize,
+    stride: usize,
+    msb_first: bool,
+    masks: [u32; 3],
+}
+
+impl PixelLayout {
+    fn decode(&self, data: &[u8], width: u32, height: u32) -> RgbaImage {
+        RgbaImage::from_fn(width, height, |x, y| {
+            let offset = y as usize * self.stride + x as usize * self.bytes_per_pixel;
+            let bytes = &data[offset..offset + self.bytes_per_pixel];
+
+            let pixel = if self.msb_first {
+                bytes.iter().fold(0u32, |acc, b| acc << 8 | *b as u32)
+            } else {
+                bytes.iter().rev().fold(0u32, |acc, b| acc << 8 | *b as u32)
+            };
+
+            let [r, g, b] = self.masks;
+            Rgba([
+                channel(pixel, r),
+                channel(pixel, g),
+                channel(pixel, b),
+                0xff,
+            ])
+        })
+    }
+}
+
+/// Extracts the bits of `mask` from `pixel` and scales them to 8 bit.
+fn channel(pixel: u32, mask: u32) -> u8 {
+    if mask == 0 {
+        return 0;
+    }
+
+    let value = (pixel & mask) >> mask.trailing_zeros();
+    let max = mask >> mask.trailing_zeros();
+
+    (value as u64 * 0xff / max as u64) as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MASKS: [u32; 3] = [0xff0000, 0x00ff00, 0x0000ff];
+
+    #[test]
+    fn pad_rounds_up() {
+        assert_eq!(pad(0, 32), 0);
+        assert_eq!(pad(1, 32), 32);
+        assert_eq!(pad(32, 32), 32);
+        assert_eq!(pad(3 * 24, 32), 96);
+        assert_eq!(pad(5 * 24, 8), 120);
+    }
+
+    #[test]
+    fn channel_scales_to_8_bit() {
+        assert_eq!(channel(0x123456, 0xff0000), 0x12);
+        assert_eq!(channel(0x123456, 0x0000ff), 0x56);
+        assert_eq!(channel(0xffff, 0), 0);
+
+        // rgb565
+        assert_eq!(channel(0xf800, 0xf800), 0xff);
+        assert_eq!(channel(0x07e0, 0x07e0), 0xff);
+        assert_eq!(channel(0x0010, 0x001f), 0x83);
+    }
+
+    #[test]
+    fn decode_32_bit() {
+        let expected = [
+            Rgba([0x12, 0x34, 0x56, 0xff]),
+            Rgba([0xab, 0xcd, 0xef, 0xff]),
+        ];
+
+        let lsb = PixelLayout {
+            bytes_per_pixel: 4,
+            stride: 8,
+            msb_first: false,
+            masks: MASKS,
+        };
+        let image = lsb.decode(&[0x56, 0x34, 0x12, 0x00, 0xef, 0xcd, 0xab, 0x00], 2, 1);
+        assert_eq!(image.pixels().copied().collect::<Vec<_>>(), expected);
+
+        let msb = PixelLayout {
+            msb_first: true,
+            ..lsb
+        };
+        let image = msb.decode(&[0x00, 0x12, 0x34, 0x56, 0x00, 0xab, 0xcd, 0xef], 2, 1);
+        assert_eq!(image.pixels().copied().collect::<Vec<_>>(), expected);
+    }
+
+    #[test]
+    fn decode_24_bit_with_padded_rows() {
+        // two pixels per row, padded to 8 bytes
+        let lsb = PixelLayout {
+            bytes_per_pixel: 3,
+            stride: pad(2 * 24, 32) / 8,
+            msb_first: false,
+            masks: MASKS,
+        };
+        let data = [
+            0x03, 0x02, 0x01, 0x06, 0x05, 0x04, 0xee, 0xee, //
+            0x09, 0x08, 0x07, 0x0c, 0x0b, 0x0a, 0xee, 0xee,
+        ];
+        let image = lsb.decode(&data, 2, 2);
+
+        assert_eq!(*image.get_pixel(0, 0), Rgba([0x01, 0x02, 0x03, 0xff]));
+        assert_eq!(*image.get_pixel(1, 0), Rgba([0x04, 0x05, 0x06, 0xff]));
+        assert_eq!(*image.get_pixel(0, 1), Rgba([0x07, 0x08, 0x09, 0xff]));
+        assert_eq!(*image.get_pixel(1, 1), Rgba([0x0a, 0x0b, 0x0c, 0xff]));
+
+        let msb = PixelLayout {
+            bytes_per_pixel: 3,
+            stride: 6,
+            msb_first: true,
+            masks: MASKS,
+        };
+        let image = msb.decode(&[0x01, 0x02, 0x03, 0x04, 0x05, 0x06], 2, 1);
+
+        assert_eq!(*image.get_pixel(0, 0), Rgba([0x01, 0x02, 0x03, 0xff]));
+        assert_eq!(*image.get_pixel(1, 0), Rgba([0x04, 0x05, 0x06, 0xff]));
+    }
+
+    /// Needs a running X server, run with `cargo test -- --ignored`.
+    #[test]
+    #[ignore]
+    fn capture_display() {
+        if std::env::var_os("DISPLAY").is_none() {
+            return;
+        }
+
+        let (conn, screen_num) = x11rb::connect(None).unwrap();
+        let screen = &conn.setup().roots[screen_num];
+
+        let image = Capture::default().capture().unwrap();
+        assert_eq!(
+            image.dimensions(),
+            (
+                screen.width_in_pixels as u32,
+                screen.height_in_pixels as u32
+            )
+        );
+
+        let monitors = randr::get_monitors(&conn, screen.root, true)
+            .unwrap()
+            .reply()
+            .unwrap()
+            .monitors;
+
+        if let Some(first) = monitors.first() {
+            let image = Capture {
+                monitor: Some(0),
+                ..Capture::default()
+            }
+            .capture()
+            .unwrap();
+            assert_eq!(
+                image.dimensions(),
+                (first.width as u32, first.height as u32)
+            );
+        }
+
+        let missing = Capture {
+            monitor: Some(monitors.len()),
+            ..Capture::default()
+        }
+        .capture();
+        assert!(matches!(missing, Err(Error::NoSuchMonitor(n)) if n == monitors.len()));
+    }
+}
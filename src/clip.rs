@@ -0,0 +1,187 @@
+use image::{Rgba, RgbaImage};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    history::Rect,
+    raster::{self, Point},
+};
+
+/// Corners of the polygon an ellipse is approximated with.
+const ELLIPSE_SEGMENTS: usize = 256;
+
+/// A crop to a shape other than a rectangle, everything outside of the shape becomes
+/// transparent.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Clip {
+    /// Ellipse filling the rectangle.
+    Ellipse(Rect),
+    /// Closed polygon through the mouse positions.
+    Lasso(Vec<Point>),
+}
+
+impl Clip {
+    /// Moves the shape by `(x, y)`, e.g. to map it from the cropped image back into the source
+    /// image.
+    pub fn offset(&self, x: u32, y: u32) -> Self {
+        match self {
+            Clip::Ellipse(rect) => Clip::Ellipse(rect.offset(x, y)),
+            Clip::Lasso(points) => Clip::Lasso(
+                points
+                    .iter()
+                    .map(|p| [p[0] + x as f64, p[1] + y as f64])
+                    .collect(),
+            ),
+        }
+    }
+
+    /// The smallest rectangle containing the shape, the image is cropped to it.
+    pub fn bounds(&self) -> Rect {
+        match self {
+            Clip::Ellipse(rect) => *rect,
+            Clip::Lasso(points) if points.is_empty() => Rect::new(0, 0, 0, 0),
+            Clip::Lasso(points) => {
+                let [min, max] = raster::bounds(points, 0.0);
+                let pixel = |v: f64| v.max(0.0) as u32;
+
+                let (x, y) = (pixel(min[0].floor()), pixel(min[1].floor()));
+                let (right, bottom) = (pixel(max[0].ceil()), pixel(max[1].ceil()));
+
+                Rect::new(x, y, right - x, bottom - y)
+            }
+        }
+    }
+
+    /// The shape as a closed polygon.
+    pub fn outline(&self) -> Vec<Point> {
+        match self {
+            Clip::Ellipse(rect) => {
+                let radius = [rect.width as f64 / 2.0, rect.height as f64 / 2.0];
+                let center = [rect.x as f64 + radius[0], rect.y as f64 + radius[1]];
+
+                (0..ELLIPSE_SEGMENTS)
+                    .map(|i| {
+                        let angle = i as f64 / ELLIPSE_SEGMENTS as f64 * std::f64::consts::TAU;
+                        [
+                            center[0] + radius[0] * angle.cos(),
+                            center[1] + radius[1] * angle.sin(),
+                        ]
+                    })
+                    .collect()
+            }
+            Clip::Lasso(points) => points.clone(),
+        }
+    }
+
+    /// Makes every pixel outside of the shape transparent, with an anti-aliased edge. `origin`
+    /// is the position of the top left corner of `image` in the coordinates of the shape.
+    pub fn apply(&self, image: &mut RgbaImage, [ox, oy]: Point) {
+        let points = self
+            .outline()
+            .iter()
+            .map(|p| [p[0] - ox, p[1] - oy])
+            .collect::<Vec<_>>();
+
+        let (width, height) = image.dimensions();
+        let edges = || points.iter().zip(points.iter().cycle().skip(1));
+
+        // the distance to the outline only matters within a pixel of it, so every edge only
+        // looks at the pixels close to it
+        let mut near = vec![f64::INFINITY; width as usize * height as usize];
+
+        for (a, b) in edges() {
+            let [min, max] = raster::bounds(&[*a, *b], 1.0);
+            let clamp = |v: f64, max: u32| (v.max(0.0) as u32).min(max);
+
+            for y in clamp(min[1].floor(), height)..clamp(max[1].ceil(), height) {
+                for x in clamp(min[0].floor(), width)..clamp(max[0].ceil(), width) {
+                    let distance =
+                        raster::segment_distance([x as f64 + 0.5, y as f64 + 0.5], *a, *b);
+                    let near = &mut near[y as usize * width as usize + x as usize];
+
+                    *near = near.min(distance);
+                }
+            }
+        }
+
+        for y in 0..height {
+            let center = y as f64 + 0.5;
+
+            // even-odd rule: a pixel is inside if the row crosses the outline an odd number of
+            // times to its left
+            let crossings = edges()
+                .filter(|(a, b)| (a[1] <= center) != (b[1] <= center))
+                .map(|(a, b)| a[0] + (center - a[1]) / (b[1] - a[1]) * (b[0] - a[0]))
+                .collect::<Vec<_>>();
+
+            for x in 0..width {
+                let left = crossings
+                    .iter()
+                    .filter(|crossing| **crossing < x as f64 + 0.5)
+                    .count();
+                let distance = near[y as usize * width as usize + x as usize];
+
+                let coverage = if left % 2 == 1 {
+                    raster::fill(-distance)
+                } else {
+                    raster::fill(distance)
+                };
+
+                let pixel = image.get_pixel_mut(x, y);
+
+                if coverage <= 0.0 {
+                    *pixel = Rgba([0, 0, 0, 0]);
+                } else {
+                    pixel[3] = (pixel[3] as f64 * coverage).round() as u8;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OPAQUE: Rgba<u8> = Rgba([200, 100, 50, 255]);
+
+    #[test]
+    fn ellipse_clears_the_corners() {
+        let mut image = RgbaImage::from_pixel(40, 20, OPAQUE);
+        Clip::Ellipse(Rect::new(0, 0, 40, 20)).apply(&mut image, [0.0, 0.0]);
+
+        for (x, y) in [(0, 0), (39, 0), (0, 19), (39, 19)] {
+            assert_eq!(image.get_pixel(x, y)[3], 0, "{} {}", x, y);
+        }
+
+        assert_eq!(*image.get_pixel(20, 10), OPAQUE);
+
+        // the edge is anti-aliased, the colour stays the same
+        let edge = image
+            .pixels()
+            .filter(|pixel| pixel[3] > 0 && pixel[3] < 255)
+            .collect::<Vec<_>>();
+
+        assert!(!edge.is_empty());
+        assert!(edge.iter().all(|pixel| pixel.0[..3] == OPAQUE.0[..3]));
+    }
+
+    #[test]
+    fn lasso_uses_the_even_odd_rule() {
+        // a pentagram around the center, its outline crosses itself
+        let points = (0..5)
+            .map(|i| {
+                let angle = (i * 2 % 5) as f64 / 5.0 * std::f64::consts::TAU;
+                [25.0 + 20.0 * angle.sin(), 25.0 - 20.0 * angle.cos()]
+            })
+            .collect();
+
+        let mut image = RgbaImage::from_pixel(50, 50, OPAQUE);
+        Clip::Lasso(points).apply(&mut image, [0.0, 0.0]);
+
+        // the tips are inside, the pentagon in the middle is crossed twice and outside
+        assert_eq!(*image.get_pixel(24, 13), OPAQUE);
+        assert_eq!(image.get_pixel(25, 25)[3], 0);
+        assert_eq!(image.get_pixel(0, 0)[3], 0);
+    }
+}
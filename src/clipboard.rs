@@ -0,0 +1,389 @@
+//! Copying images to and pasting them from the X11 CLIPBOARD selection.
+//!
+//! X11 does not store the clipboard anywhere: the client that copied something owns the
+//! selection and sends the data to everyone who asks for it, so [`serve`] keeps running until
+//! another client copies something.
+
+use std::{
+    collections::HashMap,
+    fmt, thread,
+    time::{Duration, Instant},
+};
+
+use log::info;
+use x11rb::{
+    connection::{Connection, RequestConnection},
+    errors::{ConnectError, ConnectionError, ReplyError, ReplyOrIdError},
+    protocol::{
+        xproto::{
+            Atom, AtomEnum, ChangeWindowAttributesAux, ConnectionExt as _, CreateWindowAux,
+            EventMask, GetPropertyReply, GetPropertyType, PropMode, Property, SelectionNotifyEvent,
+            SelectionRequestEvent, Time, Window, WindowClass, SELECTION_NOTIFY_EVENT,
+        },
+        Event,
+    },
+    rust_connection::RustConnection,
+    wrapper::ConnectionExt as _,
+};
+
+/// How long to wait for the owner of the clipboard to send the next part of the image.
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+x11rb::atom_manager! {
+    Atoms: AtomsCookie {
+        CLIPBOARD,
+        TARGETS,
+        INCR,
+        PNG: b"image/png",
+        CORAL_SELECTION,
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Connect(ConnectError),
+    Reply(ReplyOrIdError),
+    /// The clipboard is empty or does not hold a PNG image.
+    NoImage,
+    /// Another client kept the clipboard.
+    NotOwner,
+    /// The owner of the clipboard did not answer.
+    Timeout,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Connect(e) => write!(f, "failed to connect to X server: {}", e),
+            Error::Reply(e) => write!(f, "X request failed: {}", e),
+            Error::NoImage => write!(f, "the clipboard does not contain a png image"),
+            Error::NotOwner => write!(f, "could not take over the clipboard"),
+            Error::Timeout => write!(f, "the owner of the clipboard did not answer"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<ConnectError> for Error {
+    fn from(e: ConnectError) -> Self {
+        Error::Connect(e)
+    }
+}
+
+impl From<ConnectionError> for Error {
+    fn from(e: ConnectionError) -> Self {
+        Error::Reply(e.into())
+    }
+}
+
+impl From<ReplyError> for Error {
+    fn from(e: ReplyError) -> Self {
+        Error::Reply(e.into())
+    }
+}
+
+impl From<ReplyOrIdError> for Error {
+    fn from(e: ReplyOrIdError) -> Self {
+        Error::Reply(e)
+    }
+}
+
+/// Connection to the display in `$DISPLAY` with an invisible window that selections are
+/// exchanged through.
+struct Clipboard {
+    conn: RustConnection,
+    window: Window,
+    atoms: Atoms,
+}
+
+impl Clipboard {
+    fn connect() -> Result<Self, Error> {
+        let (conn, screen_num) = RustConnection::connect(None)?;
+        let root = conn.setup().roots[screen_num].root;
+
+        let window = conn.generate_id()?;
+        conn.create_window(
+            x11rb::COPY_DEPTH_FROM_PARENT,
+            window,
+            root,
+            0,
+            0,
+            1,
+            1,
+            0,
+            WindowClass::INPUT_ONLY,
+            x11rb::COPY_FROM_PARENT,
+            &CreateWindowAux::new().event_mask(EventMask::PROPERTY_CHANGE),
+        )?;
+
+        let atoms = Atoms::new(&conn)?.reply()?;
+
+        Ok(Self {
+            conn,
+            window,
+            atoms,
+        })
+    }
+
+    /// Waits for the next event, fails if none arrives before `deadline`.
+    fn next_event(&self, deadline: Instant) -> Result<Event, Error> {
+        loop {
+            if let Some(event) = self.conn.poll_for_event()? {
+                return Ok(event);
+            }
+
+            if Instant::now() > deadline {
+                return Err(Error::Timeout);
+            }
+
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    /// Reads and deletes the property the selection is delivered in.
+    fn take_property(&self) -> Result<GetPropertyReply, Error> {
+        let reply = self
+            .conn
+            .get_property(
+                true,
+                self.window,
+                self.atoms.CORAL_SELECTION,
+                GetPropertyType::ANY,
+                0,
+                u32::MAX,
+            )?
+            .reply()?;
+
+        Ok(reply)
+    }
+
+    /// Sends `png` to the client that requested it, or the list of formats it is available in.
+    /// Returns the property the answer was written to, `NONE` if the request was refused.
+    fn answer(
+        &self,
+        request: &SelectionRequestEvent,
+        png: &[u8],
+        transfers: &mut HashMap<(Window, Atom), usize>,
+    ) -> Result<Atom, Error> {
+        let Self { conn, atoms, .. } = self;
+
+        // obsolete clients leave out the property
+        let property = match request.property {
+            x11rb::NONE => request.target,
+            property => property,
+        };
+
+        if request.target == atoms.TARGETS {
+            conn.change_property32(
+                PropMode::REPLACE,
+                request.requestor,
+                property,
+                AtomEnum::ATOM,
+                &[atoms.TARGETS, atoms.PNG],
+            )?;
+        } else if request.target == atoms.PNG && png.len() <= chunk_size(conn) {
+            conn.change_property8(
+                PropMode::REPLACE,
+                request.requestor,
+                property,
+                atoms.PNG,
+                png,
+            )?;
+        } else if request.target == atoms.PNG {
+            // too large for a single request, the requestor asks for every chunk by deleting
+            // the previous one
+            conn.change_window_attributes(
+                request.requestor,
+                &ChangeWindowAttributesAux::new().event_mask(EventMask::PROPERTY_CHANGE),
+            )?;
+            conn.change_property32(
+                PropMode::REPLACE,
+                request.requestor,
+                property,
+                atoms.INCR,
+                &[png.len() as u32],
+            )?;
+
+            transfers.insert((request.requestor, property), 0);
+        } else {
+            return Ok(x11rb::NONE);
+        }
+
+        Ok(property)
+    }
+}
+
+/// Largest part of the image that is sent in a single request.
+fn chunk_size(conn: &RustConnection) -> usize {
+    conn.maximum_request_bytes() / 4
+}
+
+/// Reads the PNG image in the clipboard.
+pub fn paste() -> Result<Vec<u8>, Error> {
+    let clipboard = Clipboard::connect()?;
+    let Clipboard {
+        conn,
+        window,
+        atoms,
+    } = &clipboard;
+
+    conn.convert_selection(
+        *window,
+        atoms.CLIPBOARD,
+        atoms.PNG,
+        atoms.CORAL_SELECTION,
+        Time::CURRENT_TIME,
+    )?;
+    conn.flush()?;
+
+    let deadline = Instant::now() + TIMEOUT;
+    loop {
+        match clipboard.next_event(deadline)? {
+            Event::SelectionNotify(event) if event.property == x11rb::NONE => {
+                return Err(Error::NoImage)
+            }
+            Event::SelectionNotify(_) => break,
+            _ => {}
+        }
+    }
+
+    // deleting the `INCR` property starts the transfer of a large image
+    let reply = clipboard.take_property()?;
+    if reply.type_ != atoms.INCR {
+        return Ok(reply.value);
+    }
+
+    let mut data = Vec::new();
+    let mut deadline = Instant::now() + TIMEOUT;
+
+    loop {
+        match clipboard.next_event(deadline)? {
+            Event::PropertyNotify(event)
+                if event.atom == atoms.CORAL_SELECTION && event.state == Property::NEW_VALUE =>
+            {
+                let chunk = clipboard.take_property()?.value;
+
+                // an empty chunk ends the transfer
+                if chunk.is_empty() {
+                    return Ok(data);
+                }
+
+                data.extend(chunk);
+                deadline = Instant::now() + TIMEOUT;
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Owns the clipboard and hands out `png` until another client takes it over. `ready` is
+/// called as soon as the clipboard is owned.
+pub fn serve<F: FnOnce()>(png: &[u8], ready: F) -> Result<(), Error> {
+    let clipboard = Clipboard::connect()?;
+    let Clipboard {
+        conn,
+        window,
+        atoms,
+    } = &clipboard;
+
+    conn.set_selection_owner(*window, atoms.CLIPBOARD, Time::CURRENT_TIME)?;
+    if conn.get_selection_owner(atoms.CLIPBOARD)?.reply()?.owner != *window {
+        return Err(Error::NotOwner);
+    }
+
+    info!("serving {} bytes on the clipboard..", png.len());
+    ready();
+
+    // offset of the next chunk of every transfer in progress, by requestor and property
+    let mut transfers = HashMap::new();
+
+    loop {
+        match conn.wait_for_event()? {
+            Event::SelectionClear(event) if event.selection == atoms.CLIPBOARD => {
+                info!("the clipboard was taken over");
+                return Ok(());
+            }
+            Event::SelectionRequest(request) => {
+                let property = clipboard.answer(&request, png, &mut transfers)?;
+
+                let notify = SelectionNotifyEvent {
+                    response_type: SELECTION_NOTIFY_EVENT,
+                    sequence: 0,
+                    time: request.time,
+                    requestor: request.requestor,
+                    selection: request.selection,
+                    target: request.target,
+                    property,
+                };
+                conn.send_event(false, request.requestor, EventMask::NO_EVENT, notify)?;
+                conn.flush()?;
+            }
+            Event::PropertyNotify(event) if event.state == Property::DELETE => {
+                let key = (event.window, event.atom);
+
+                if let Some(offset) = transfers.get(&key).copied() {
+                    let end = usize::min(offset + chunk_size(conn), png.len());
+                    conn.change_property8(
+                        PropMode::REPLACE,
+                        event.window,
+                        event.atom,
+                        atoms.PNG,
+                        &png[offset..end],
+                    )?;
+                    conn.flush()?;
+
+                    // the empty chunk after the last one ends the transfer
+                    if offset == end {
+                        transfers.remove(&key);
+                    } else {
+                        transfers.insert(key, end);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc;
+
+    use super::*;
+
+    /// Serves `data` on a thread and reads it back.
+    fn round_trip(data: Vec<u8>) -> thread::JoinHandle<Result<(), Error>> {
+        let (ready, wait) = mpsc::channel();
+        let served = data.clone();
+        let server = thread::spawn(move || serve(&served, || ready.send(()).unwrap()));
+
+        wait.recv_timeout(TIMEOUT)
+            .expect("the clipboard was not owned");
+        // not `assert_eq`, a failure would print megabytes
+        assert!(paste().unwrap() == data);
+
+        server
+    }
+
+    /// Needs a running X server, run with `cargo test -- --ignored`.
+    #[test]
+    #[ignore]
+    fn serve_and_paste() {
+        if std::env::var_os("DISPLAY").is_none() {
+            return;
+        }
+
+        let chunk = chunk_size(&RustConnection::connect(None).unwrap().0);
+        // pseudo random bytes, so a transfer that skips or repeats a chunk is noticed
+        let bytes = |len: usize| (0..len).map(|i| (i * 7919 % 251) as u8).collect::<Vec<_>>();
+
+        let small = round_trip(bytes(1000));
+        // sent in chunks with INCR
+        // the second server keeps the clipboard until the test exits
+        let _large = round_trip(bytes(2 * chunk + 123));
+
+        // serving the large image took the clipboard over from the first server
+        small.join().unwrap().unwrap();
+    }
+}
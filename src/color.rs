@@ -0,0 +1,108 @@
+use image::Rgba;
+
+/// Colours that can be selected with the number keys in the editor.
+pub const PALETTE: [Rgba<u8>; 9] = [
+    Rgba([0xe5, 0x39, 0x35, 0xff]), // red
+    Rgba([0xfb, 0x8c, 0x00, 0xff]), // orange
+    Rgba([0xfd, 0xd8, 0x35, 0xff]), // yellow
+    Rgba([0x43, 0xa0, 0x47, 0xff]), // green
+    Rgba([0x1e, 0x88, 0xe5, 0xff]), // blue
+    Rgba([0x8e, 0x24, 0xaa, 0xff]), // purple
+    Rgba([0xd8, 0x1b, 0x60, 0xff]), // pink
+    Rgba([0x00, 0x00, 0x00, 0xff]), // black
+    Rgba([0xff, 0xff, 0xff, 0xff]), // white
+];
+
+/// Parses a hex colour like `#f00`, `#ff0000` or `#ff000080`, the `#` is optional.
+pub fn parse(s: &str) -> Result<Rgba<u8>, String> {
+    let hex = s.strip_prefix('#').unwrap_or(s);
+
+    let digits = hex
+        .chars()
+        .map(|c| c.to_digit(16).map(|d| d as u8))
+        .collect::<Option<Vec<_>>>()
+        .ok_or_else(|| format!("`{}` is not a hex colour", s))?;
+
+    let channels = match digits.len() {
+        3 | 4 => digits.iter().map(|d| d << 4 | d).collect::<Vec<_>>(),
+        6 | 8 => digits.chunks(2).map(|d| d[0] << 4 | d[1]).collect(),
+        _ => return Err(format!("`{}` is not a hex colour", s)),
+    };
+
+    Ok(Rgba([
+        channels[0],
+        channels[1],
+        channels[2],
+        channels.get(3).copied().unwrap_or(0xff),
+    ]))
+}
+
+/// Black or white, whichever is easier to read on top of `color`.
+pub fn contrasting(Rgba([r, g, b, _]): Rgba<u8>) -> Rgba<u8> {
+    let luma = 0.299 * r as f64 + 0.587 * g as f64 + 0.114 * b as f64;
+
+    if luma > 150.0 {
+        PALETTE[7]
+    } else {
+        PALETTE[8]
+    }
+}
+
+/// Converts a colour for use with `graphics`.
+pub fn to_f32(Rgba([r, g, b, a]): Rgba<u8>) -> [f32; 4] {
+    [r, g, b, a].map(|c| c as f32 / 255.0)
+}
+
+/// Formats a colour as `#RRGGBBAA`.
+pub fn to_hex(Rgba([r, g, b, a]): Rgba<u8>) -> String {
+    format!("#{:02X}{:02X}{:02X}{:02X}", r, g, b, a)
+}
+
+/// Stores colours as `#RRGGBBAA` strings, for `#[serde(with = "color::hex")]`.
+pub mod hex {
+    use image::Rgba;
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(color: &Rgba<u8>, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&super::to_hex(*color))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Rgba<u8>, D::Error> {
+        super::parse(&String::deserialize(deserializer)?).map_err(D::Error::custom)
+    }
+}
+
+/// Formats a colour as `rgb(r, g, b)`, ignoring its alpha.
+pub fn to_rgb(Rgba([r, g, b, _]): Rgba<u8>) -> String {
+    format!("rgb({}, {}, {})", r, g, b)
+}
+
+/// Formats a colour as `hsl(h, s%, l%)`, ignoring its alpha.
+pub fn to_hsl(Rgba([r, g, b, _]): Rgba<u8>) -> String {
+    let [r, g, b] = [r, g, b].map(|c| c as f64 / 255.0);
+
+    let max = f64::max(r, f64::max(g, b));
+    let min = f64::min(r, f64::min(g, b));
+    let (delta, lightness) = (max - min, (max + min) / 2.0);
+
+    let (hue, saturation) = if delta == 0.0 {
+        (0.0, 0.0)
+    } else {
+        let hue = if max == r {
+            ((g - b) / delta).rem_euclid(6.0)
+        } else if max == g {
+            (b - r) / delta + 2.0
+        } else {
+            (r - g) / delta + 4.0
+        };
+
+        (hue * 60.0, delta / (1.0 - (2.0 * lightness - 1.0).abs()))
+    };
+
+    format!(
+        "hsl({}, {}%, {}%)",
+        hue.round() as u32 % 360,
+        (saturation * 100.0).round(),
+        (lightness * 100.0).round()
+    )
+}
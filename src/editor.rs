@@ -0,0 +1,1210 @@
+use log::{info, warn};
+use piston::{
+    keyboard::ModifierKey, Button, ButtonArgs, ButtonState, GenericEvent, Key, MouseButton,
+};
+use vecmath::{mat2x3_id, mat2x3_inv, row_mat2x3_mul, row_mat2x3_transform_pos2, Matrix2x3};
+
+use crate::annotation::{Annotation, Shape, Style};
+use crate::clip::Clip;
+use crate::color::PALETTE;
+use crate::history::{Edit, Rect};
+use crate::raster;
+use crate::redact::Redaction;
+use crate::selection::{Constraint, Handle, Selection};
+
+/// Distance in window pixels at which a selection handle can be grabbed.
+const HANDLE_TOLERANCE: f64 = 8.0;
+/// Distance in window pixels the mouse has to move to add a point to a freehand stroke.
+const STROKE_STEP: f64 = 2.0;
+
+/// What the application should do in response to an input event.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    Edit(Edit),
+    Undo,
+    Redo,
+    /// Read the colour of a pixel in the edited image.
+    Pick(u32, u32),
+    /// Remove the step marker at a position in image coordinates.
+    DeleteMarker([f64; 2]),
+    Save,
+    Quit,
+}
+
+/// What dragging with the left mouse button does.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Tool {
+    Crop,
+    Rectangle,
+    Arrow,
+    Line,
+    /// Click to place a label, then type it.
+    Text,
+    /// Hides the selected region instead of cropping to it.
+    Redact(Redaction),
+    /// Click to print the colour of a pixel.
+    Pick,
+    /// Drag to measure a distance.
+    Measure,
+    Pen,
+    Highlighter,
+    /// Click to place the next numbered step marker, right click to remove one.
+    Marker,
+    /// Crops to the ellipse inside the selection.
+    CropEllipse,
+    /// Crops to a freehand shape.
+    CropLasso,
+}
+
+impl Tool {
+    fn shape(self, from: [f64; 2], to: [f64; 2]) -> Option<Shape> {
+        match self {
+            Tool::Rectangle => Some(Shape::Rectangle { from, to }),
+            Tool::Arrow => Some(Shape::Arrow { from, to }),
+            Tool::Line => Some(Shape::Line { from, to }),
+            _ => None,
+        }
+    }
+
+    /// The edit made by dragging a freehand tool through `points`.
+    fn stroke(self, points: Vec<[f64; 2]>, style: Style, image_size: (u32, u32)) -> Option<Edit> {
+        match self {
+            Tool::Pen => Some(Edit::Annotate(Annotation::new(
+                Shape::Freehand { points },
+                style,
+            ))),
+            Tool::Highlighter => Some(Edit::Annotate(Annotation::new(
+                Shape::Highlight { points },
+                style,
+            ))),
+            Tool::CropLasso => lasso_clip(points, image_size).map(Edit::Clip),
+            _ => None,
+        }
+    }
+
+    /// Whether the tool works on a rectangular selection.
+    fn selects(self) -> bool {
+        matches!(self, Tool::Crop | Tool::Redact(_) | Tool::CropEllipse)
+    }
+
+    fn is_freehand(self) -> bool {
+        matches!(self, Tool::Pen | Tool::Highlighter | Tool::CropLasso)
+    }
+}
+
+/// Maps image pixel coordinates to window coordinates, the image is centered and scaled to fit
+/// into the window, then zoomed and panned by the user.
+#[derive(Debug, Clone, Copy)]
+pub struct View {
+    pub window_size: [f64; 2],
+    pub image_size: (u32, u32),
+    pub fullscreen: bool,
+    /// Scale relative to fitting the image into the window.
+    pub zoom: f64,
+    /// Offset of the image center from the window center, in window pixels.
+    pub pan: [f64; 2],
+}
+
+impl View {
+    /// Window pixels per image pixel.
+    pub fn scale(&self) -> f64 {
+        let [window_width, window_height] = self.window_size;
+        let (image_width, image_height) = self.image_size;
+
+        let (ratio_width, ratio_height) = (
+            window_width / image_width as f64,
+            window_height / image_height as f64,
+        );
+
+        let ratio = f64::min(ratio_width, ratio_height) * if self.fullscreen { 1.0 } else { 0.95 };
+
+        ratio * self.zoom
+    }
+
+    pub fn transform(&self) -> Matrix2x3<f64> {
+        let [window_width, window_height] = self.window_size;
+        let (image_width, image_height) = self.image_size;
+
+        [
+            translate([
+                window_width / 2.0 + self.pan[0],
+                window_height / 2.0 + self.pan[1],
+            ]),
+            scale(self.scale()),
+            translate([
+                0.0 - (image_width / 2) as f64,
+                0.0 - (image_height / 2) as f64,
+            ]),
+        ]
+        .iter()
+        .fold(mat2x3_id(), |acc, m| row_mat2x3_mul(acc, *m))
+    }
+
+    pub fn to_image(self, pos: [f64; 2]) -> [f64; 2] {
+        row_mat2x3_transform_pos2(mat2x3_inv(self.transform()), pos)
+    }
+
+    pub fn to_window(self, pos: [f64; 2]) -> [f64; 2] {
+        row_mat2x3_transform_pos2(self.transform(), pos)
+    }
+
+    /// Multiplies the zoom by `factor`, keeping the image pixel below `anchor` (in window
+    /// coordinates) in place.
+    pub fn zoom_at(&mut self, anchor: [f64; 2], factor: f64) {
+        let fixed = self.to_image(anchor);
+
+        self.zoom = (self.zoom * factor).clamp(0.1, 100.0);
+
+        let moved = self.to_window(fixed);
+        self.pan = [
+            self.pan[0] + anchor[0] - moved[0],
+            self.pan[1] + anchor[1] - moved[1],
+        ];
+    }
+
+    pub fn reset(&mut self) {
+        self.zoom = 1.0;
+        self.pan = [0.0, 0.0];
+    }
+}
+
+fn translate([x, y]: [f64; 2]) -> Matrix2x3<f64> {
+    [[1.0, 0.0, x], [0.0, 1.0, y]]
+}
+
+fn scale(s: f64) -> Matrix2x3<f64> {
+    [[s, 0.0, 0.0], [0.0, s, 0.0]]
+}
+
+/// Converts a selection between two points in image coordinates into a crop rectangle clamped
+/// to the image bounds. Selections without any area inside the image yield `None`.
+pub fn crop_rect(
+    a: [f64; 2],
+    b: [f64; 2],
+    (image_width, image_height): (u32, u32),
+) -> Option<Rect> {
+    use std::cmp::{max, min};
+
+    let clamp = |pos: [f64; 2]| {
+        (
+            min(image_width, f64::max(0.0, pos[0]) as u32),
+            min(image_height, f64::max(0.0, pos[1]) as u32),
+        )
+    };
+
+    let (a, b) = (clamp(a), clamp(b));
+
+    let rect = Rect::new(
+        min(a.0, b.0),
+        min(a.1, b.1),
+        max(a.0, b.0) - min(a.0, b.0),
+        max(a.1, b.1) - min(a.1, b.1),
+    );
+
+    if rect.is_empty() {
+        None
+    } else {
+        Some(rect)
+    }
+}
+
+/// Converts a lasso through `points` in image coordinates into a clip. Like [`crop_rect`], a
+/// lasso without any area inside the image, e.g. along a straight line or drawn entirely
+/// next to the image, yields `None`.
+pub fn lasso_clip(points: Vec<[f64; 2]>, (image_width, image_height): (u32, u32)) -> Option<Clip> {
+    if points.len() < 3 || raster::polygon_area(&points) < 1.0 {
+        return None;
+    }
+
+    let clip = Clip::Lasso(points);
+    let image = Rect::new(0, 0, image_width, image_height);
+
+    if clip.bounds().intersect(&image).is_empty() {
+        None
+    } else {
+        Some(clip)
+    }
+}
+
+/// What dragging with the left mouse button does to the selection.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Grab {
+    Resize(Handle),
+    /// Last cursor position in image coordinates.
+    Move([f64; 2]),
+}
+
+/// Horizontal, vertical and direct distance between two points in image pixels.
+pub fn describe_distance(from: [f64; 2], to: [f64; 2]) -> String {
+    let (dx, dy) = ((to[0] - from[0]).abs(), (to[1] - from[1]).abs());
+
+    format!("dx {}  dy {}  {:.1} px", dx, dy, dx.hypot(dy))
+}
+
+/// Input handling of the editor, independent of any window or graphics backend.
+///
+/// Positions are stored in image coordinates so they stay valid if the window is resized or the
+/// view is zoomed while selecting.
+pub struct Editor {
+    view: View,
+    tool: Tool,
+    style: Style,
+    /// Last used redaction method, restored when switching back to the redaction tool.
+    redaction: Redaction,
+    /// Start of the annotation that is being drawn.
+    drag_start: Option<[f64; 2]>,
+    /// Mouse positions of the freehand stroke that is being drawn.
+    stroke: Vec<[f64; 2]>,
+    /// Crop or redaction region, it can be adjusted until it is confirmed with enter.
+    selection: Option<Selection>,
+    grab: Option<Grab>,
+    /// Last measurement, it is burned into the image as a dimension line when confirmed.
+    measured: Option<([f64; 2], [f64; 2])>,
+    constraint: Option<Constraint>,
+    /// Edge or corner moved by the arrow keys, the whole selection is moved if it is `None`.
+    active: Option<Handle>,
+    /// Position and content of the label that is being typed.
+    text: Option<([f64; 2], String)>,
+    /// Mouse position in window coordinates.
+    mouse: Option<[f64; 2]>,
+    /// Last mouse position while dragging the view around.
+    panning: Option<[f64; 2]>,
+    space: bool,
+    /// Whether the magnifier is shown around the cursor.
+    loupe: bool,
+    modifiers: ModifierKey,
+}
+
+impl Editor {
+    pub fn new(
+        image_size: (u32, u32),
+        fullscreen: bool,
+        style: Style,
+        redaction: Redaction,
+        constraint: Option<Constraint>,
+    ) -> Self {
+        let editor = Self {
+            view: View {
+                window_size: [image_size.0 as f64, image_size.1 as f64],
+                image_size,
+                fullscreen,
+                zoom: 1.0,
+                pan: [0.0, 0.0],
+            },
+            tool: Tool::Crop,
+            style,
+            redaction,
+            drag_start: None,
+            stroke: Vec::new(),
+            selection: None,
+            grab: None,
+            measured: None,
+            constraint,
+            active: None,
+            text: None,
+            mouse: None,
+            panning: None,
+            space: false,
+            loupe: false,
+            modifiers: ModifierKey::NO_MODIFIER,
+        };
+
+        editor.check_constraint();
+        editor
+    }
+
+    pub fn tool(&self) -> Tool {
+        self.tool
+    }
+
+    pub fn view(&self) -> &View {
+        &self.view
+    }
+
+    /// Has to be called whenever the edited image changed; positions in the old image are
+    /// meaningless afterwards.
+    pub fn set_image_size(&mut self, image_size: (u32, u32)) {
+        if image_size != self.view.image_size {
+            self.view.image_size = image_size;
+            self.view.reset();
+            self.check_constraint();
+        }
+
+        self.cancel();
+    }
+
+    /// Drops the selection and any annotation that is being drawn.
+    fn cancel(&mut self) {
+        self.drag_start = None;
+        self.stroke.clear();
+        self.selection = None;
+        self.grab = None;
+        self.measured = None;
+    }
+
+    /// Mouse position in window coordinates.
+    pub fn mouse(&self) -> Option<[f64; 2]> {
+        self.mouse
+    }
+
+    /// The magnifier is always shown while picking colours.
+    pub fn loupe(&self) -> bool {
+        self.loupe || self.tool == Tool::Pick
+    }
+
+    /// The image pixel below the mouse, if there is one.
+    pub fn pixel(&self) -> Option<(u32, u32)> {
+        let [x, y] = self.cursor()?;
+        let (width, height) = self.view.image_size;
+
+        if x >= 0.0 && y >= 0.0 && x < width as f64 && y < height as f64 {
+            Some((x as u32, y as u32))
+        } else {
+            None
+        }
+    }
+
+    /// Mouse position in image coordinates.
+    fn cursor(&self) -> Option<[f64; 2]> {
+        self.mouse.map(|pos| self.view.to_image(pos))
+    }
+
+    /// The current crop or redaction selection as corners in window coordinates.
+    pub fn selection(&self) -> Option<([f64; 2], [f64; 2])> {
+        self.selection.map(|selection| {
+            (
+                self.view.to_window(selection.from()),
+                self.view.to_window(selection.to()),
+            )
+        })
+    }
+
+    /// The outline of the lasso that is being drawn, in window coordinates.
+    pub fn lasso(&self) -> Option<Vec<[f64; 2]>> {
+        if self.tool != Tool::CropLasso || self.stroke.is_empty() {
+            return None;
+        }
+
+        Some(
+            self.stroke
+                .iter()
+                .map(|p| self.view.to_window(*p))
+                .collect(),
+        )
+    }
+
+    /// The area the current selection would crop, in image pixels.
+    pub fn selection_rect(&self) -> Option<Rect> {
+        let selection = self.selection?;
+        crop_rect(selection.from(), selection.to(), self.view.image_size)
+    }
+
+    /// The distance that is being measured or was measured last, snapped to pixel corners.
+    pub fn measurement(&self) -> Option<([f64; 2], [f64; 2])> {
+        if self.tool != Tool::Measure {
+            return None;
+        }
+
+        let snap = |[x, y]: [f64; 2]| [x.round(), y.round()];
+
+        match (self.drag_start, self.cursor()) {
+            (Some(from), Some(to)) => Some((snap(from), snap(to))),
+            _ => self.measured,
+        }
+    }
+
+    /// The annotation that is currently being drawn, in image coordinates.
+    pub fn preview(&self) -> Option<Annotation> {
+        if let Some((at, text)) = &self.text {
+            let text = format!("{}|", text);
+            return Some(Annotation::new(Shape::Text { at: *at, text }, self.style));
+        }
+
+        if let Some((from, to)) = self.measurement() {
+            return Some(Annotation::new(Shape::Dimension { from, to }, self.style));
+        }
+
+        if !self.stroke.is_empty() {
+            return match self
+                .tool
+                .stroke(self.stroke.clone(), self.style, self.view.image_size)?
+            {
+                Edit::Annotate(annotation) => Some(annotation),
+                _ => None,
+            };
+        }
+
+        let (from, to) = (self.drag_start?, self.cursor()?);
+
+        self.tool
+            .shape(from, to)
+            .map(|shape| Annotation::new(shape, self.style))
+    }
+
+    pub fn event<E: GenericEvent>(&mut self, e: &E) -> Option<Command> {
+        self.modifiers.event(e);
+
+        if let Some(args) = e.render_args() {
+            self.view.window_size = args.window_size;
+        }
+
+        if let Some(pos) = e.mouse_cursor_args() {
+            if let Some(last) = &mut self.panning {
+                self.view.pan[0] += pos[0] - last[0];
+                self.view.pan[1] += pos[1] - last[1];
+                *last = pos;
+            }
+
+            self.mouse = Some(pos);
+            self.drag();
+            self.sketch();
+        }
+
+        if let (Some([_, dy]), Some(mouse)) = (e.mouse_scroll_args(), self.mouse) {
+            self.view.zoom_at(mouse, 1.2f64.powf(dy));
+        }
+
+        if let (Some(typed), Some((_, text))) = (e.text_args(), &mut self.text) {
+            text.extend(typed.chars().filter(|c| !c.is_control()));
+        }
+
+        e.button_args().and_then(|args| self.button(args))
+    }
+
+    /// Extends the freehand stroke to the cursor.
+    fn sketch(&mut self) {
+        let (last, cursor) = match (self.stroke.last(), self.cursor()) {
+            (Some(last), Some(cursor)) => (*last, cursor),
+            _ => return,
+        };
+
+        if raster::distance(last, cursor) * self.view.scale() >= STROKE_STEP {
+            self.stroke.push(cursor);
+        }
+    }
+
+    /// Moves or resizes the selection with the cursor.
+    fn drag(&mut self) {
+        let (cursor, constraint) = (self.cursor(), self.drag_constraint());
+        let bounds = self.bounds();
+        let (grab, selection, cursor) = match (self.grab, &mut self.selection, cursor) {
+            (Some(grab), Some(selection), Some(cursor)) => (grab, selection, cursor),
+            _ => return,
+        };
+
+        match grab {
+            Grab::Resize(handle) => {
+                selection.resize(handle, cursor);
+
+                if let Some(constraint) = constraint {
+                    selection.constrain(handle, constraint, bounds);
+                }
+            }
+            Grab::Move(last) => {
+                selection.translate([cursor[0] - last[0], cursor[1] - last[1]]);
+                self.grab = Some(Grab::Move(cursor));
+
+                // cropping a constrained selection at the border would break its shape
+                if constraint.is_some() {
+                    selection.shift_inside(bounds);
+                }
+            }
+        }
+    }
+
+    /// Grabs a handle or the inside of the selection, or starts a new one at the cursor.
+    fn grab(&mut self, cursor: [f64; 2]) {
+        let tolerance = HANDLE_TOLERANCE / self.view.scale();
+
+        let grab =
+            self.selection
+                .and_then(|selection| match selection.handle_at(cursor, tolerance) {
+                    Some(handle) => Some(Grab::Resize(handle)),
+                    None if selection.contains(cursor) => Some(Grab::Move(cursor)),
+                    None => None,
+                });
+
+        self.grab = match grab {
+            Some(grab) => Some(grab),
+            None => {
+                let mut selection = Selection::new(cursor);
+
+                // a fixed size selection is complete without dragging
+                if let Some(constraint) = self.drag_constraint() {
+                    selection.constrain(Handle::NEW, constraint, self.bounds());
+                }
+
+                self.selection = Some(selection);
+                Some(Grab::Resize(Handle::NEW))
+            }
+        };
+
+        self.active = match self.grab {
+            Some(Grab::Resize(handle)) => Some(handle),
+            _ => None,
+        };
+    }
+
+    /// Moves the active edge or corner by `delta` image pixels, starting a new selection at the
+    /// cursor if there is none.
+    fn nudge(&mut self, delta: [f64; 2]) {
+        if self.selection.is_none() {
+            let at = self
+                .cursor()
+                .map_or([0.0, 0.0], |[x, y]| [x.floor(), y.floor()]);
+
+            self.selection = Some(Selection::new(at));
+            self.active = Some(Handle::NEW);
+        }
+
+        let bounds = self.bounds();
+        if let Some(selection) = &mut self.selection {
+            selection.nudge(self.active, delta);
+
+            match (self.active, self.constraint) {
+                (Some(handle), Some(constraint)) => selection.constrain(handle, constraint, bounds),
+                (None, Some(_)) => selection.shift_inside(bounds),
+                _ => {}
+            }
+        }
+    }
+
+    /// Size of the image as a position, selections are kept inside of it.
+    fn bounds(&self) -> [f64; 2] {
+        let (width, height) = self.view.image_size;
+        [width as f64, height as f64]
+    }
+
+    /// A fixed size selection can not fit into a smaller image.
+    fn check_constraint(&self) {
+        let (width, height) = self.view.image_size;
+
+        if let Some(Constraint::Size(w, h)) = self.constraint {
+            if w > width || h > height {
+                warn!(
+                    "the selection size {}x{} is larger than the image {}x{}, it will be cropped",
+                    w, h, width, height
+                );
+            }
+        }
+    }
+
+    /// Holding shift while dragging keeps the selection square.
+    fn drag_constraint(&self) -> Option<Constraint> {
+        if self.modifiers.contains(ModifierKey::SHIFT) {
+            Some(Constraint::Ratio(1.0))
+        } else {
+            self.constraint
+        }
+    }
+
+    /// Turns the selection into a crop or redaction, or burns the measurement into the image.
+    fn confirm(&mut self) -> Option<Command> {
+        if let Some((from, to)) = self.measured.take() {
+            let dimension = Annotation::new(Shape::Dimension { from, to }, self.style);
+            return Some(Command::Edit(Edit::Annotate(dimension)));
+        }
+
+        let rect = self.selection_rect();
+        self.selection = None;
+        self.grab = None;
+
+        let rect = rect?;
+
+        match self.tool {
+            Tool::Redact(redaction) => Some(Command::Edit(Edit::Redact(rect, redaction))),
+            Tool::CropEllipse => Some(Command::Edit(Edit::Clip(Clip::Ellipse(rect)))),
+            _ => Some(Command::Edit(Edit::Crop(rect))),
+        }
+    }
+
+    fn button(&mut self, args: ButtonArgs) -> Option<Command> {
+        // while typing, keys are text and not shortcuts
+        if let (Some(_), Button::Keyboard(key)) = (&self.text, args.button) {
+            return self.type_key(key, args.state);
+        }
+
+        match args {
+            ButtonArgs {
+                state: ButtonState::Press,
+                button: Button::Mouse(button),
+                ..
+            } if button == MouseButton::Middle || (button == MouseButton::Left && self.space) => {
+                self.panning = self.mouse;
+                None
+            }
+            ButtonArgs {
+                state: ButtonState::Release,
+                button: Button::Mouse(_),
+                ..
+            } if self.panning.is_some() => {
+                self.panning = None;
+                None
+            }
+            ButtonArgs {
+                state,
+                button: Button::Keyboard(Key::Space),
+                ..
+            } => {
+                self.space = state == ButtonState::Press;
+                None
+            }
+            ButtonArgs {
+                state: ButtonState::Press,
+                button: Button::Mouse(MouseButton::Left),
+                ..
+            } if self.tool == Tool::Pick => self.pixel().map(|(x, y)| Command::Pick(x, y)),
+            ButtonArgs {
+                state: ButtonState::Press,
+                button: Button::Mouse(MouseButton::Left),
+                ..
+            } if self.tool == Tool::Marker => self.cursor().map(|at| {
+                let marker = Shape::Marker { at, number: 0 };
+                Command::Edit(Edit::Annotate(Annotation::new(marker, self.style)))
+            }),
+            ButtonArgs {
+                state: ButtonState::Press,
+                button: Button::Mouse(MouseButton::Right),
+                ..
+            } if self.tool == Tool::Marker => self.cursor().map(Command::DeleteMarker),
+            ButtonArgs {
+                state: ButtonState::Press,
+                button: Button::Mouse(MouseButton::Left),
+                ..
+            } if self.tool == Tool::Text => {
+                let commit = self.commit_text();
+                self.text = self.cursor().map(|cursor| (cursor, String::new()));
+
+                commit
+            }
+            ButtonArgs {
+                state: ButtonState::Press,
+                button: Button::Mouse(MouseButton::Left),
+                ..
+            } => {
+                match (self.cursor(), self.tool) {
+                    (Some(cursor), tool) if tool.selects() => self.grab(cursor),
+                    (Some(cursor), tool) if tool.is_freehand() => self.stroke = vec![cursor],
+                    (Some(cursor), _) => self.drag_start = Some(cursor),
+                    _ => {}
+                }
+
+                None
+            }
+            ButtonArgs {
+                state: ButtonState::Release,
+                button: Button::Mouse(MouseButton::Left),
+                ..
+            } if self.grab.is_some() => {
+                self.grab = None;
+
+                // a click without dragging does not select anything
+                if matches!(self.selection, Some(selection) if selection.is_empty()) {
+                    self.selection = None;
+                }
+
+                None
+            }
+            ButtonArgs {
+                state: ButtonState::Release,
+                button: Button::Mouse(MouseButton::Left),
+                ..
+            } if self.tool == Tool::Measure => {
+                self.measured = self.measurement().filter(|(from, to)| from != to);
+                self.drag_start = None;
+
+                if let Some((from, to)) = self.measured {
+                    info!("measured {}", describe_distance(from, to));
+                }
+
+                None
+            }
+            ButtonArgs {
+                state: ButtonState::Release,
+                button: Button::Mouse(MouseButton::Left),
+                ..
+            } if !self.stroke.is_empty() => {
+                let points = std::mem::take(&mut self.stroke);
+
+                self.tool
+                    .stroke(points, self.style, self.view.image_size)
+                    .map(Command::Edit)
+            }
+            ButtonArgs {
+                state: ButtonState::Release,
+                button: Button::Mouse(MouseButton::Left),
+                ..
+            } => match (self.drag_start.take(), self.cursor()) {
+                // a click without dragging does not draw anything
+                (Some(start), Some(end)) if start != end => self
+                    .tool
+                    .shape(start, end)
+                    .map(|shape| Command::Edit(Edit::Annotate(Annotation::new(shape, self.style)))),
+                _ => None,
+            },
+            ButtonArgs {
+                state: ButtonState::Release,
+                button: Button::Keyboard(Key::Return),
+                ..
+            }
+            | ButtonArgs {
+                state: ButtonState::Release,
+                button: Button::Keyboard(Key::NumPadEnter),
+                ..
+            } => self.confirm(),
+            ButtonArgs {
+                state: ButtonState::Press,
+                button: Button::Keyboard(key @ (Key::Left | Key::Right | Key::Up | Key::Down)),
+                ..
+            } if self.tool.selects() => {
+                let step = if self.modifiers.contains(ModifierKey::SHIFT) {
+                    10.0
+                } else {
+                    1.0
+                };
+
+                self.nudge(match key {
+                    Key::Left => [-step, 0.0],
+                    Key::Right => [step, 0.0],
+                    Key::Up => [0.0, -step],
+                    _ => [0.0, step],
+                });
+
+                None
+            }
+            ButtonArgs {
+                state: ButtonState::Press,
+                button: Button::Keyboard(Key::Tab),
+                ..
+            } if self.selection.is_some() => {
+                self.active = match self.active {
+                    Some(handle) => Handle::ALL
+                        .iter()
+                        .skip_while(|h| **h != handle)
+                        .nth(1)
+                        .copied(),
+                    None => Some(Handle::ALL[0]),
+                };
+
+                info!("nudging: {:?}", self.active);
+                None
+            }
+            ButtonArgs {
+                state: ButtonState::Press,
+                button: Button::Keyboard(Key::Z),
+                ..
+            } if self.modifiers.contains(ModifierKey::CTRL) => {
+                if self.modifiers.contains(ModifierKey::SHIFT) {
+                    Some(Command::Redo)
+                } else {
+                    Some(Command::Undo)
+                }
+            }
+            ButtonArgs {
+                state: ButtonState::Press,
+                button: Button::Keyboard(key),
+                ..
+            } if !self.modifiers.contains(ModifierKey::CTRL) => {
+                self.shortcut(key);
+                None
+            }
+            ButtonArgs {
+                state: ButtonState::Release,
+                button: Button::Keyboard(Key::W),
+                ..
+            } => {
+                if self.selecting() {
+                    self.cancel();
+                    None
+                } else {
+                    Some(Command::Save)
+                }
+            }
+            ButtonArgs {
+                state: ButtonState::Release,
+                button: Button::Keyboard(Key::Q),
+                ..
+            }
+            | ButtonArgs {
+                state: ButtonState::Release,
+                button: Button::Keyboard(Key::Escape),
+                ..
+            } => {
+                if self.selecting() {
+                    self.cancel();
+                    None
+                } else {
+                    Some(Command::Quit)
+                }
+            }
+            _ => None,
+        }
+    }
+
+    fn selecting(&self) -> bool {
+        self.drag_start.is_some()
+            || !self.stroke.is_empty()
+            || self.selection.is_some()
+            || self.measured.is_some()
+    }
+
+    fn type_key(&mut self, key: Key, state: ButtonState) -> Option<Command> {
+        match (key, state) {
+            (Key::Backspace, ButtonState::Press) => {
+                if let Some((_, text)) = &mut self.text {
+                    text.pop();
+                }
+
+                None
+            }
+            // act on release, otherwise the release would be handled as a shortcut
+            (Key::Return, ButtonState::Release) | (Key::NumPadEnter, ButtonState::Release) => {
+                self.commit_text()
+            }
+            (Key::Escape, ButtonState::Release) => {
+                self.text = None;
+                None
+            }
+            _ => None,
+        }
+    }
+
+    fn commit_text(&mut self) -> Option<Command> {
+        match self.text.take() {
+            Some((at, text)) if !text.trim().is_empty() => Some(Command::Edit(Edit::Annotate(
+                Annotation::new(Shape::Text { at, text }, self.style),
+            ))),
+            _ => None,
+        }
+    }
+
+    /// View, tool, colour, stroke width and font size selection.
+    fn shortcut(&mut self, key: Key) {
+        match key {
+            Key::D0 => {
+                info!("reset view");
+                self.view.reset();
+                return;
+            }
+            Key::M => {
+                self.loupe = !self.loupe;
+                info!("loupe: {}", self.loupe);
+                return;
+            }
+            _ => {}
+        }
+
+        let tool = match key {
+            Key::C => Some(Tool::Crop),
+            Key::R => Some(Tool::Rectangle),
+            Key::A => Some(Tool::Arrow),
+            Key::L => Some(Tool::Line),
+            Key::T => Some(Tool::Text),
+            Key::I => Some(Tool::Pick),
+            Key::D => Some(Tool::Measure),
+            Key::P => Some(Tool::Pen),
+            Key::H => Some(Tool::Highlighter),
+            Key::N => Some(Tool::Marker),
+            Key::E => Some(Tool::CropEllipse),
+            Key::O => Some(Tool::CropLasso),
+            // pressing it again cycles through the redaction methods
+            Key::X => match self.tool {
+                Tool::Redact(redaction) => Some(Tool::Redact(redaction.next())),
+                _ => Some(Tool::Redact(self.redaction)),
+            },
+            _ => None,
+        };
+
+        if let Some(tool) = tool {
+            info!("tool: {:?}", tool);
+            self.tool = tool;
+
+            if let Tool::Redact(redaction) = tool {
+                self.redaction = redaction;
+            }
+
+            self.cancel();
+            return;
+        }
+
+        let color = match key {
+            Key::D1 => Some(0),
+            Key::D2 => Some(1),
+            Key::D3 => Some(2),
+            Key::D4 => Some(3),
+            Key::D5 => Some(4),
+            Key::D6 => Some(5),
+            Key::D7 => Some(6),
+            Key::D8 => Some(7),
+            Key::D9 => Some(8),
+            _ => None,
+        };
+
+        if let Some(index) = color {
+            self.style.color = PALETTE[index];
+            info!("color: {:?}", self.style.color);
+            return;
+        }
+
+        let step = match key {
+            Key::Plus | Key::Equals | Key::NumPadPlus => 1.0,
+            Key::Minus | Key::NumPadMinus => -1.0,
+            _ => return,
+        };
+
+        // markers are sized by the font size of their number
+        if matches!(self.tool, Tool::Text | Tool::Marker) {
+            self.style.font_size = (self.style.font_size + step * 2.0).clamp(8.0, 256.0);
+            info!("font size: {}", self.style.font_size);
+        } else {
+            self.style.width = (self.style.width + step).clamp(1.0, 64.0);
+            info!("stroke width: {}", self.style.width);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use piston::{Event, Input, Motion};
+
+    use super::*;
+
+    const SIZE: (u32, u32) = (100, 50);
+
+    fn editor() -> Editor {
+        Editor::new(SIZE, false, Style::default(), Redaction::default(), None)
+    }
+
+    fn send(editor: &mut Editor, input: Input) -> Option<Command> {
+        editor.event(&Event::Input(input, None))
+    }
+
+    /// Moves the mouse to `pos` in image coordinates.
+    fn move_to(editor: &mut Editor, pos: [f64; 2]) -> Option<Command> {
+        let pos = editor.view().to_window(pos);
+        send(editor, Input::Move(Motion::MouseCursor(pos)))
+    }
+
+    fn button(editor: &mut Editor, button: Button, state: ButtonState) -> Option<Command> {
+        let args = ButtonArgs {
+            state,
+            button,
+            scancode: None,
+        };
+
+        send(editor, Input::Button(args))
+    }
+
+    fn click(editor: &mut Editor, button: Button) -> Option<Command> {
+        self::button(editor, button, ButtonState::Press)
+            .or_else(|| self::button(editor, button, ButtonState::Release))
+    }
+
+    /// Drags a selection from `from` to `to` and confirms it with enter.
+    fn select(editor: &mut Editor, from: [f64; 2], to: [f64; 2]) -> Option<Command> {
+        let left = Button::Mouse(MouseButton::Left);
+
+        assert_eq!(move_to(editor, from), None);
+        assert_eq!(button(editor, left, ButtonState::Press), None);
+        assert_eq!(move_to(editor, to), None);
+        assert_eq!(button(editor, left, ButtonState::Release), None);
+
+        click(editor, Button::Keyboard(Key::Return))
+    }
+
+    #[test]
+    fn dragging_and_enter_crops() {
+        let mut editor = editor();
+
+        assert_eq!(
+            select(&mut editor, [10.5, 5.5], [40.5, 25.5]),
+            Some(Command::Edit(Edit::Crop(Rect::new(10, 5, 30, 20))))
+        );
+
+        // the selection is gone after confirming it
+        assert_eq!(click(&mut editor, Button::Keyboard(Key::Return)), None);
+    }
+
+    #[test]
+    fn selections_follow_zoom_and_pan() {
+        let mut editor = editor();
+
+        move_to(&mut editor, [20.0, 10.0]);
+        send(&mut editor, Input::Move(Motion::MouseScroll([0.0, 3.0])));
+        assert!(editor.view().zoom > 1.5);
+
+        let middle = Button::Mouse(MouseButton::Middle);
+        let (start, pan) = (editor.mouse().unwrap(), editor.view().pan);
+
+        button(&mut editor, middle, ButtonState::Press);
+        send(
+            &mut editor,
+            Input::Move(Motion::MouseCursor([start[0] - 30.0, start[1] + 20.0])),
+        );
+        button(&mut editor, middle, ButtonState::Release);
+
+        let before = editor.view().pan;
+        assert!(
+            (before[0] - pan[0] + 30.0).abs() < 1e-9 && (before[1] - pan[1] - 20.0).abs() < 1e-9
+        );
+
+        // moving the mouse does not pan once the button is released
+        assert_eq!(
+            select(&mut editor, [60.5, 30.5], [15.5, 45.5]),
+            Some(Command::Edit(Edit::Crop(Rect::new(15, 30, 45, 15))))
+        );
+        assert_eq!(editor.view().pan, before);
+    }
+
+    #[test]
+    fn crop_rect_orders_corners() {
+        let expected = Some(Rect::new(10, 5, 30, 20));
+
+        assert_eq!(crop_rect([10.0, 5.0], [40.0, 25.0], SIZE), expected);
+        assert_eq!(crop_rect([40.0, 25.0], [10.0, 5.0], SIZE), expected);
+        assert_eq!(crop_rect([40.0, 5.0], [10.0, 25.0], SIZE), expected);
+    }
+
+    #[test]
+    fn crop_rect_clamps_to_image() {
+        assert_eq!(
+            crop_rect([-20.0, -10.0], [30.0, 20.0], SIZE),
+            Some(Rect::new(0, 0, 30, 20))
+        );
+        assert_eq!(
+            crop_rect([60.0, 40.0], [500.0, 300.0], SIZE),
+            Some(Rect::new(60, 40, 40, 10))
+        );
+        assert_eq!(
+            crop_rect([-5.0, -5.0], [105.0, 55.0], SIZE),
+            Some(Rect::new(0, 0, 100, 50))
+        );
+    }
+
+    #[test]
+    fn crop_rect_rejects_empty_selections() {
+        assert_eq!(crop_rect([10.0, 10.0], [10.0, 10.0], SIZE), None);
+        assert_eq!(crop_rect([10.0, 10.0], [10.0, 40.0], SIZE), None);
+        assert_eq!(crop_rect([-20.0, -20.0], [-5.0, 30.0], SIZE), None);
+        assert_eq!(crop_rect([120.0, 10.0], [150.0, 30.0], SIZE), None);
+    }
+
+    fn keys(editor: &mut Editor, keys: &[Key]) {
+        for key in keys {
+            assert_eq!(click(editor, Button::Keyboard(*key)), None);
+        }
+    }
+
+    fn shift(editor: &mut Editor, state: ButtonState) {
+        button(editor, Button::Keyboard(Key::LShift), state);
+    }
+
+    #[test]
+    fn arrows_select_and_tab_cycles_handles() {
+        let mut editor = editor();
+        move_to(&mut editor, [10.7, 5.2]);
+
+        // the selection starts at the pixel below the mouse, shift moves by 10 pixels
+        shift(&mut editor, ButtonState::Press);
+        keys(
+            &mut editor,
+            &[Key::Right, Key::Right, Key::Right, Key::Down, Key::Down],
+        );
+        shift(&mut editor, ButtonState::Release);
+
+        // the first tab switches to the opposite corner
+        keys(&mut editor, &[Key::Tab, Key::Left, Key::Up]);
+        assert_eq!(editor.selection_rect(), Some(Rect::new(9, 4, 31, 21)));
+
+        // after the last edge the whole selection moves
+        keys(&mut editor, &[Key::Tab; 7]);
+        shift(&mut editor, ButtonState::Press);
+        keys(&mut editor, &[Key::Down]);
+        shift(&mut editor, ButtonState::Release);
+
+        assert_eq!(
+            click(&mut editor, Button::Keyboard(Key::Return)),
+            Some(Command::Edit(Edit::Crop(Rect::new(9, 14, 31, 21))))
+        );
+    }
+
+    #[test]
+    fn nudging_rounds_to_whole_pixels() {
+        let mut editor = editor();
+        let left = Button::Mouse(MouseButton::Left);
+
+        move_to(&mut editor, [10.6, 5.4]);
+        button(&mut editor, left, ButtonState::Press);
+        move_to(&mut editor, [40.4, 25.6]);
+        button(&mut editor, left, ButtonState::Release);
+
+        // the dragged corner stays active, both corners snap before it moves
+        keys(&mut editor, &[Key::Right]);
+
+        assert_eq!(
+            click(&mut editor, Button::Keyboard(Key::Return)),
+            Some(Command::Edit(Edit::Crop(Rect::new(11, 5, 30, 21))))
+        );
+    }
+
+    #[test]
+    fn lasso_clip_rejects_degenerate_shapes() {
+        let triangle = vec![[10.0, 10.0], [40.0, 10.0], [10.0, 30.0]];
+        assert_eq!(
+            lasso_clip(triangle.clone(), SIZE),
+            Some(Clip::Lasso(triangle))
+        );
+
+        assert_eq!(lasso_clip(vec![[10.0, 10.0], [40.0, 10.0]], SIZE), None);
+        assert_eq!(
+            lasso_clip(vec![[10.0, 10.0], [20.0, 20.0], [30.0, 30.0]], SIZE),
+            None
+        );
+        assert_eq!(
+            lasso_clip(vec![[-40.0, 10.0], [-10.0, 10.0], [-10.0, 30.0]], SIZE),
+            None
+        );
+        assert_eq!(
+            lasso_clip(vec![[110.0, 60.0], [140.0, 60.0], [140.0, 90.0]], SIZE),
+            None
+        );
+    }
+
+    #[test]
+    fn view_round_trip() {
+        let mut view = View {
+            window_size: [800.0, 600.0],
+            image_size: (640, 480),
+            fullscreen: false,
+            zoom: 1.0,
+            pan: [0.0, 0.0],
+        };
+
+        view.zoom_at([200.0, 150.0], 2.5);
+        view.pan = [view.pan[0] + 33.0, view.pan[1] - 17.0];
+
+        for p in [[0.0, 0.0], [123.5, 45.25], [640.0, 480.0], [-10.0, 700.0]] {
+            let q = view.to_image(view.to_window(p));
+
+            assert!((q[0] - p[0]).abs() < 1e-9 && (q[1] - p[1]).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn zoom_keeps_anchor_in_place() {
+        let mut view = View {
+            window_size: [800.0, 600.0],
+            image_size: (640, 480),
+            fullscreen: true,
+            zoom: 1.0,
+            pan: [10.0, -20.0],
+        };
+
+        let anchor = [250.0, 420.0];
+        let before = view.to_image(anchor);
+        view.zoom_at(anchor, 3.0);
+        let after = view.to_image(anchor);
+
+        assert!((before[0] - after[0]).abs() < 1e-9 && (before[1] - after[1]).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,44 @@
+use rusttype::{point, Font, Scale};
+
+/// Font used for all text, bundled so text looks the same everywhere.
+pub const DEJAVU_SANS: &[u8] = include_bytes!("../assets/DejaVuSans.ttf");
+
+pub fn font() -> Font<'static> {
+    Font::try_from_bytes(DEJAVU_SANS).expect("bundled font is valid")
+}
+
+/// Calls `coverage` with the position and coverage of every pixel of `text`, starting at the
+/// baseline position `origin`.
+pub fn layout<F>(text: &str, size: f64, origin: [f64; 2], mut coverage: F)
+where
+    F: FnMut(i32, i32, f32),
+{
+    let font = font();
+    let scale = Scale::uniform(size as f32);
+
+    for glyph in font.layout(text, scale, point(origin[0] as f32, origin[1] as f32)) {
+        if let Some(bounds) = glyph.pixel_bounding_box() {
+            glyph.draw(|x, y, v| coverage(bounds.min.x + x as i32, bounds.min.y + y as i32, v));
+        }
+    }
+}
+
+/// The em size of the font when it is drawn `size` pixels high, as used by CSS and SVG.
+pub fn em_size(size: f64) -> f64 {
+    let font = font();
+    let metrics = font.v_metrics_unscaled();
+
+    size * font.units_per_em() as f64 / (metrics.ascent - metrics.descent) as f64
+}
+
+/// Advance width of `text` in pixels.
+pub fn width(text: &str, size: f64) -> f64 {
+    let font = font();
+    let scale = Scale::uniform(size as f32);
+
+    font.layout(text, scale, point(0.0, 0.0))
+        .last()
+        .map_or(0.0, |glyph| {
+            (glyph.position().x + glyph.unpositioned().h_metrics().advance_width) as f64
+        })
+}
@@ -0,0 +1,490 @@
+use std::{fmt, str::FromStr};
+
+use image::{imageops, RgbaImage};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    annotation::{Annotation, Shape},
+    clip::Clip,
+    ops::{parse_size, parse_u32},
+    redact::Redaction,
+};
+
+/// An axis aligned rectangle in image pixel coordinates.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Rect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Rect {
+    pub fn new(x: u32, y: u32, width: u32, height: u32) -> Self {
+        Self {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    pub fn to_json(self) -> String {
+        format!(
+            r#"{{"x":{},"y":{},"width":{},"height":{}}}"#,
+            self.x, self.y, self.width, self.height
+        )
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.width == 0 || self.height == 0
+    }
+
+    /// Moves the rectangle by `(x, y)`, e.g. to map it from the cropped image back into the
+    /// source image.
+    pub fn offset(&self, x: u32, y: u32) -> Self {
+        Self::new(
+            self.x.saturating_add(x),
+            self.y.saturating_add(y),
+            self.width,
+            self.height,
+        )
+    }
+
+    /// The overlapping area of both rectangles, empty if they do not overlap.
+    pub fn intersect(&self, other: &Rect) -> Self {
+        use std::cmp::{max, min};
+
+        let x = max(self.x, other.x);
+        let y = max(self.y, other.y);
+        let right = min(
+            self.x.saturating_add(self.width),
+            other.x.saturating_add(other.width),
+        );
+        let bottom = min(
+            self.y.saturating_add(self.height),
+            other.y.saturating_add(other.height),
+        );
+
+        Self::new(x, y, right.saturating_sub(x), bottom.saturating_sub(y))
+    }
+}
+
+impl fmt::Display for Rect {
+    /// X11 style geometry: `WxH+X+Y`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}x{}+{}+{}", self.width, self.height, self.x, self.y)
+    }
+}
+
+impl FromStr for Rect {
+    type Err = String;
+
+    /// Parses X11 style geometry: `WxH+X+Y`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("expected `WxH+X+Y`, got `{}`", s);
+
+        let mut parts = s.splitn(3, '+');
+        let size = parts.next().ok_or_else(invalid)?;
+        let x = parts.next().ok_or_else(invalid)?;
+        let y = parts.next().ok_or_else(invalid)?;
+
+        match parse_size(size)? {
+            (Some(width), Some(height)) => {
+                Ok(Rect::new(parse_u32(x)?, parse_u32(y)?, width, height))
+            }
+            _ => Err(invalid()),
+        }
+    }
+}
+
+/// An operation that changes the edited image.
+///
+/// All coordinates are relative to the untouched source image, so an edit is only a few
+/// bytes and the image can always be rebuilt from the source.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Edit {
+    Crop(Rect),
+    Annotate(Annotation),
+    Redact(Rect, Redaction),
+    /// Removes the n-th of the annotations drawn so far.
+    Delete(usize),
+    /// Crops to the bounds of the shape and makes everything outside of it transparent.
+    Clip(Clip),
+}
+
+/// Undo/redo stack of every [`Edit`] applied to an image.
+///
+/// Instead of keeping a copy of the image for every step only the source image and the list
+/// of edits are stored; undoing an edit replays the remaining ones on top of the source.
+#[derive(Clone)]
+pub struct History {
+    source: RgbaImage,
+    edits: Vec<Edit>,
+    undone: Vec<Edit>,
+}
+
+impl History {
+    pub fn new(source: RgbaImage) -> Self {
+        Self {
+            source,
+            edits: Vec::new(),
+            undone: Vec::new(),
+        }
+    }
+
+    /// Continues editing `source` after `edits`, e.g. from a project file.
+    pub fn from_edits(source: RgbaImage, edits: Vec<Edit>) -> Self {
+        Self {
+            source,
+            edits,
+            undone: Vec::new(),
+        }
+    }
+
+    /// The image before any edit.
+    pub fn source(&self) -> &RgbaImage {
+        &self.source
+    }
+
+    /// The same edits on a source image that has the redactions applied already, so the
+    /// redacted pixels are gone even if the redactions are undone. Redacting the redacted
+    /// pixels again leaves a fill unchanged and makes blurring and pixelating a bit stronger.
+    pub fn bake_redactions(&self) -> Self {
+        let mut source = self.source.clone();
+
+        for edit in &self.edits {
+            if let Edit::Redact(rect, redaction) = edit {
+                redaction.apply(&mut source, *rect);
+            }
+        }
+
+        Self::from_edits(source, self.edits.clone())
+    }
+
+    /// Every edit that was not undone, oldest first.
+    pub fn edits(&self) -> &[Edit] {
+        &self.edits
+    }
+
+    /// The visible part of the source image after all crops.
+    pub fn crop(&self) -> Rect {
+        self.crop_after(&self.edits)
+    }
+
+    fn crop_after(&self, edits: &[Edit]) -> Rect {
+        let (width, height) = self.source.dimensions();
+
+        edits
+            .iter()
+            .fold(Rect::new(0, 0, width, height), |crop, edit| match edit {
+                Edit::Crop(rect) => crop.intersect(rect),
+                Edit::Clip(clip) => crop.intersect(&clip.bounds()),
+                _ => crop,
+            })
+    }
+
+    /// Records a new edit, which invalidates everything that could have been redone.
+    pub fn push(&mut self, edit: Edit) {
+        self.edits.push(edit);
+        self.undone.clear();
+    }
+
+    /// Reverts the last edit, returns `false` if there was nothing to undo.
+    pub fn undo(&mut self) -> bool {
+        match self.edits.pop() {
+            Some(edit) => {
+                self.undone.push(edit);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Re-applies the last undone edit, returns `false` if there was nothing to redo.
+    pub fn redo(&mut self) -> bool {
+        match self.undone.pop() {
+            Some(edit) => {
+                self.edits.push(edit);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// All annotations in source image coordinates, in the order they were drawn. Markers are
+    /// numbered in the same order, so deleting one renumbers the following ones.
+    pub fn annotations(&self) -> Vec<Annotation> {
+        annotations(&self.edits)
+            .into_iter()
+            .map(|(_, annotation)| annotation)
+            .collect()
+    }
+
+    /// The annotations that are drawn on top of [`History::render`], the ones made before a
+    /// redaction are part of the image already. They are the last ones of
+    /// [`History::annotations`].
+    pub fn overlay(&self) -> Vec<Annotation> {
+        overlay(&self.edits)
+    }
+
+    /// The index for [`Edit::Delete`] of the topmost step marker at `at` in source image
+    /// coordinates. Redacted markers are part of the image and can not be deleted.
+    pub fn marker_at(&self, at: [f64; 2]) -> Option<usize> {
+        let (annotations, overlay) = (self.annotations(), self.overlay());
+
+        overlay
+            .iter()
+            .rposition(|annotation| annotation.marker_contains(at))
+            .map(|index| annotations.len() - overlay.len() + index)
+    }
+
+    /// Applies all edits that change pixels to the source image, annotations are left out so
+    /// they can be drawn on top unless they were redacted.
+    pub fn render(&self) -> RgbaImage {
+        let mut image = self.redacted(&self.edits);
+        self.clip(&self.edits, &mut image);
+
+        image
+    }
+
+    /// The cropped source image with all redactions applied. Annotations made before a
+    /// redaction are drawn into the image first, so they are hidden as well.
+    fn redacted(&self, edits: &[Edit]) -> RgbaImage {
+        let crop = self.crop_after(edits);
+
+        let mut redactions = edits
+            .iter()
+            .enumerate()
+            .filter_map(|(i, edit)| match edit {
+                Edit::Redact(rect, redaction) => Some((i, rect, redaction)),
+                _ => None,
+            })
+            .peekable();
+
+        if redactions.peek().is_none() {
+            return imageops::crop_imm(&self.source, crop.x, crop.y, crop.width, crop.height)
+                .to_image();
+        }
+
+        // redact the source image so the result does not depend on later crops
+        let mut image = self.source.clone();
+        let mut annotations = annotations(edits).into_iter().peekable();
+
+        for (i, rect, redaction) in redactions {
+            while let Some((_, annotation)) = annotations.next_if(|(at, _)| *at < i) {
+                annotation.rasterize(&mut image);
+            }
+
+            redaction.apply(&mut image, *rect);
+        }
+
+        imageops::crop_imm(&image, crop.x, crop.y, crop.width, crop.height).to_image()
+    }
+
+    /// The shapes the image is clipped to, in source image coordinates.
+    pub fn clips(&self) -> Vec<Clip> {
+        clips(&self.edits)
+    }
+
+    /// Makes everything outside of the clip shapes transparent.
+    fn clip(&self, edits: &[Edit], image: &mut RgbaImage) {
+        let crop = self.crop_after(edits);
+
+        for clip in clips(edits) {
+            clip.apply(image, [crop.x as f64, crop.y as f64]);
+        }
+    }
+
+    /// The final image with the annotations drawn into it.
+    pub fn flatten(&self) -> RgbaImage {
+        self.flatten_after(&self.edits)
+    }
+
+    /// Like [`History::flatten`] with one more edit, without pushing it. The source image is
+    /// not copied, unlike for a cloned history.
+    pub fn flatten_with(&self, edit: &Edit) -> RgbaImage {
+        let mut edits = self.edits.clone();
+        edits.push(edit.clone());
+
+        self.flatten_after(&edits)
+    }
+
+    fn flatten_after(&self, edits: &[Edit]) -> RgbaImage {
+        let crop = self.crop_after(edits);
+        let mut image = self.redacted(edits);
+
+        for annotation in overlay(edits) {
+            annotation
+                .translate([-(crop.x as f64), -(crop.y as f64)])
+                .rasterize(&mut image);
+        }
+
+        // annotations must not reach outside of the clip shapes either
+        self.clip(edits, &mut image);
+
+        image
+    }
+}
+
+/// The annotations left after `edits` with the index of the edit that made them, see
+/// [`History::annotations`].
+fn annotations(edits: &[Edit]) -> Vec<(usize, Annotation)> {
+    let mut annotations = Vec::new();
+
+    for (i, edit) in edits.iter().enumerate() {
+        match edit {
+            Edit::Annotate(annotation) => annotations.push((i, annotation.clone())),
+            Edit::Delete(index) if *index < annotations.len() => {
+                annotations.remove(*index);
+            }
+            _ => {}
+        }
+    }
+
+    let mut count = 0;
+    for (_, annotation) in &mut annotations {
+        if let Shape::Marker { number, .. } = &mut annotation.shape {
+            count += 1;
+            *number = count;
+        }
+    }
+
+    annotations
+}
+
+/// The annotations after the last redaction.
+fn overlay(edits: &[Edit]) -> Vec<Annotation> {
+    let redacted = edits
+        .iter()
+        .rposition(|edit| matches!(edit, Edit::Redact(..)));
+
+    annotations(edits)
+        .into_iter()
+        .filter(|(at, _)| redacted.is_none_or(|redacted| *at > redacted))
+        .map(|(_, annotation)| annotation)
+        .collect()
+}
+
+fn clips(edits: &[Edit]) -> Vec<Clip> {
+    edits
+        .iter()
+        .filter_map(|edit| match edit {
+            Edit::Clip(clip) => Some(clip.clone()),
+            _ => None,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use image::Rgba;
+
+    use super::*;
+    use crate::annotation::Style;
+
+    fn gradient() -> RgbaImage {
+        RgbaImage::from_fn(60, 40, |x, y| Rgba([x as u8 * 4, y as u8 * 6, 128, 255]))
+    }
+
+    #[test]
+    fn parses_geometry() {
+        let rect = Rect::new(3, 4, 10, 20);
+
+        assert_eq!("10x20+3+4".parse(), Ok(rect));
+        assert_eq!(rect.to_string().parse(), Ok(rect));
+        assert!("10x20+3".parse::<Rect>().is_err());
+        assert!("x20+3+4".parse::<Rect>().is_err());
+    }
+
+    #[test]
+    fn flatten_with_matches_push() {
+        let mut history = History::new(gradient());
+        history.push(Edit::Crop(Rect::new(5, 5, 50, 30)));
+
+        let region = Edit::Crop(Rect::new(10, 8, 20, 12));
+        let flattened = history.flatten_with(&region);
+
+        history.push(region);
+        assert_eq!(flattened, history.flatten());
+        assert_eq!(flattened.dimensions(), (20, 12));
+    }
+
+    #[test]
+    fn redaction_hides_earlier_annotations() {
+        let style = Style {
+            color: Rgba([255, 0, 0, 255]),
+            ..Style::default()
+        };
+        let line = |y: f64| {
+            Edit::Annotate(Annotation::new(
+                Shape::Line {
+                    from: [5.0, y],
+                    to: [55.0, y],
+                },
+                style,
+            ))
+        };
+
+        let mut history = History::new(gradient());
+        history.push(line(10.0));
+        history.push(Edit::Redact(Rect::new(0, 0, 60, 20), Redaction::Fill));
+        history.push(line(15.0));
+
+        assert_eq!(history.annotations().len(), 2);
+        assert_eq!(history.overlay(), vec![history.annotations()[1].clone()]);
+
+        let image = history.flatten();
+        assert_eq!(*image.get_pixel(30, 10), Rgba([0, 0, 0, 255]));
+        assert_eq!(*image.get_pixel(30, 15), Rgba([255, 0, 0, 255]));
+        assert_eq!(history.render().get_pixel(30, 10), image.get_pixel(30, 10));
+    }
+
+    fn marker(x: f64) -> Edit {
+        let marker = Shape::Marker {
+            at: [x, 20.0],
+            number: 0,
+        };
+
+        Edit::Annotate(Annotation::new(marker, Style::default()))
+    }
+
+    fn numbers(history: &History) -> Vec<u32> {
+        history
+            .annotations()
+            .iter()
+            .filter_map(|annotation| match annotation.shape {
+                Shape::Marker { number, .. } => Some(number),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn deleting_a_marker_renumbers_the_rest() {
+        let mut history = History::new(gradient());
+        for x in [10.0, 30.0, 50.0] {
+            history.push(marker(x));
+        }
+
+        assert_eq!(numbers(&history), vec![1, 2, 3]);
+        assert_eq!(history.marker_at([30.0, 20.0]), Some(1));
+
+        history.push(Edit::Delete(0));
+        assert_eq!(numbers(&history), vec![1, 2]);
+        assert_eq!(history.marker_at([10.0, 20.0]), None);
+        assert_eq!(history.marker_at([50.0, 20.0]), Some(1));
+    }
+
+    #[test]
+    fn redacted_markers_can_not_be_deleted() {
+        let mut history = History::new(gradient());
+        history.push(marker(10.0));
+        history.push(Edit::Redact(Rect::new(0, 0, 20, 40), Redaction::Fill));
+        history.push(marker(40.0));
+
+        assert_eq!(history.marker_at([10.0, 20.0]), None);
+        assert_eq!(history.marker_at([40.0, 20.0]), Some(1));
+    }
+}
@@ -1,90 +1,258 @@
+mod annotation;
+mod capture;
+mod clip;
+mod clipboard;
+mod color;
+mod editor;
+mod font;
+mod history;
+mod ops;
+mod output;
+mod project;
+mod raster;
+mod redact;
+mod selection;
+mod svg;
+mod template;
+
 use std::{
-    io::{Cursor, Read, Result},
-    path::PathBuf,
+    env,
+    io::{self, BufRead, BufReader, Cursor, Read, Result, Write},
+    os::unix::process::CommandExt,
+    path::{Path, PathBuf},
+    process::{self, Stdio},
+    time::Duration,
 };
 
 use glutin_window::GlutinWindow;
-use graphics::math::Matrix2d;
-use image::{imageops, DynamicImage, ImageOutputFormat, ImageResult, RgbaImage};
+use graphics::{character::CharacterCache, math::Matrix2d, Context, Transformed};
+use image::{
+    error::{ParameterError, ParameterErrorKind},
+    DynamicImage, GenericImageView, ImageError, ImageResult, Rgba, RgbaImage,
+};
 use log::{debug, error, info, warn};
 
-use opengl_graphics::{GlGraphics, OpenGL, Texture, TextureSettings};
+use opengl_graphics::{Filter, GlGraphics, GlyphCache, OpenGL, Texture, TextureSettings};
+use piston::window::WindowSettings;
 use piston::{
     event_loop::{EventSettings, Events},
-    Button, ButtonState, Key, MouseButton, MouseCursorEvent, Window,
+    Window,
 };
 use piston::{
     input::{RenderArgs, RenderEvent, UpdateArgs, UpdateEvent},
-    ButtonEvent,
+    GenericEvent,
 };
-use piston::{window::WindowSettings, ButtonArgs};
-use vecmath::{mat2x3_id, mat2x3_inv, row_mat2x3_transform_pos2};
+
+use annotation::{Annotation, Outline, Style};
+use capture::Capture;
+use editor::{Command, Editor, Tool};
+use history::{Edit, History};
+use ops::Operation;
+use output::{Encoding, Format};
+use raster::Mode;
+use redact::Redaction;
+use selection::Constraint;
+use template::Template;
 
 pub struct App {
     config: Config,
     gl: GlGraphics, // OpenGL drawing backend.
+    history: History,
+    editor: Editor,
     image: RgbaImage,
     texture: Texture,
-    area_selection: (Option<[f64; 2]>, Option<[f64; 2]>),
-    last_mouse_pos: Option<[f64; 2]>,
+    glyphs: GlyphCache<'static>,
+    /// Colours picked with the eyedropper, printed on exit.
+    picks: Vec<Rgba<u8>>,
+    /// Crops queued with `--regions`, in source image coordinates, with the number of edits in
+    /// the history when they were queued so undo goes back in order.
+    regions: Vec<(usize, Edit)>,
+    /// Regions removed by undo, in the same form, so redo can queue them again.
+    unqueued: Vec<(usize, Edit)>,
+    /// Whether `--select-only` printed a selection.
+    selected: bool,
 }
 
 impl App {
-    fn new(gl: GlGraphics, config: Config) -> Self {
-        let image = config.open_image().unwrap();
-
-        let texture = Texture::from_image(&image, &TextureSettings::new());
+    fn new(gl: GlGraphics, config: Config, history: History) -> Self {
+        let image = history.render();
+        let texture = Texture::from_image(&image, &image_texture_settings());
 
         Self {
-            config,
             gl,
+            history,
+            editor: Editor::new(
+                image.dimensions(),
+                config.force_fullscreen,
+                config.style,
+                config.redaction,
+                config.constraint,
+            ),
+            config,
             image,
             texture,
-            area_selection: (None, None),
-            last_mouse_pos: None,
+            glyphs: GlyphCache::from_bytes(font::DEJAVU_SANS, (), TextureSettings::new())
+                .expect("bundled font is valid"),
+            picks: Vec::new(),
+            regions: Vec::new(),
+            unqueued: Vec::new(),
+            selected: false,
         }
     }
 
     fn load_texture(&mut self) {
-        self.texture = Texture::from_image(&self.image, &TextureSettings::new());
+        self.texture = Texture::from_image(&self.image, &image_texture_settings());
+    }
+
+    fn reload_image(&mut self) {
+        self.image = self.history.render();
+        self.editor.set_image_size(self.image.dimensions());
+        self.load_texture();
+    }
+
+    /// Maps an edit from the visible image, which the editor works on, to the source image.
+    fn to_source(&self, edit: Edit) -> Edit {
+        let crop = self.history.crop();
+
+        match edit {
+            Edit::Crop(rect) => {
+                info!("Crop: {:#?}", rect);
+                Edit::Crop(rect.offset(crop.x, crop.y))
+            }
+            Edit::Annotate(annotation) => {
+                Edit::Annotate(annotation.translate([crop.x as f64, crop.y as f64]))
+            }
+            Edit::Redact(rect, redaction) => {
+                info!("Redact ({:?}): {:#?}", redaction, rect);
+                Edit::Redact(rect.offset(crop.x, crop.y), redaction)
+            }
+            Edit::Delete(index) => Edit::Delete(index),
+            Edit::Clip(clip) => {
+                info!("Clip: {:#?}", clip);
+                Edit::Clip(clip.offset(crop.x, crop.y))
+            }
+        }
+    }
+
+    fn apply(&mut self, edit: Edit) {
+        let edit = self.to_source(edit);
+
+        self.history.push(edit);
+        self.unqueued.clear();
+        self.reload_image();
+    }
+
+    /// Remembers a crop for saving later and keeps editing the whole image.
+    fn queue_region(&mut self, edit: Edit) {
+        let edit = self.to_source(edit);
+
+        self.regions.push((self.history.edits().len(), edit));
+        self.unqueued.clear();
+        info!("queued region {}", self.regions.len());
+    }
+
+    /// The queued regions cut out of the edited image, with everything drawn on top of it.
+    fn regions(&self) -> Vec<RgbaImage> {
+        self.regions
+            .iter()
+            .map(|(_, region)| self.history.flatten_with(region))
+            .collect()
+    }
+
+    fn undo(&mut self) {
+        // the last region was queued after the last edit
+        if let Some((edits, _)) = self.regions.last() {
+            if *edits >= self.history.edits().len() {
+                self.unqueued.extend(self.regions.pop());
+                info!("unqueued region {}", self.regions.len() + 1);
+                return;
+            }
+        }
+
+        if self.history.undo() {
+            info!("undo");
+            self.reload_image();
+        }
+    }
+
+    fn redo(&mut self) {
+        // the last unqueued region was queued before the next edit to redo
+        if let Some((edits, _)) = self.unqueued.last() {
+            if *edits == self.history.edits().len() {
+                self.regions.extend(self.unqueued.pop());
+                info!("queued region {}", self.regions.len());
+                return;
+            }
+        }
+
+        if self.history.redo() {
+            info!("redo");
+            self.reload_image();
+        }
+    }
+
+    fn delete_marker(&mut self, [x, y]: [f64; 2]) {
+        let crop = self.history.crop();
+        let at = [x + crop.x as f64, y + crop.y as f64];
+
+        if let Some(index) = self.history.marker_at(at) {
+            info!("delete marker");
+            self.apply(Edit::Delete(index));
+        }
+    }
+
+    fn pick(&mut self, x: u32, y: u32) {
+        let color = *self.image.get_pixel(x, y);
+
+        info!("picked {}", describe_color(color));
+        self.picks.push(color);
+    }
+
+    /// Prints the picked colours to `stdout`, unless the image is printed there.
+    fn print_picks(&self) {
+        for color in &self.picks {
+            if self.config.prints_image() {
+                eprintln!("{}", describe_color(*color));
+            } else {
+                println!("{}", describe_color(*color));
+            }
+        }
     }
 
     fn render(&mut self, args: &RenderArgs) {
         let Self {
             gl,
             texture,
-            area_selection,
-            last_mouse_pos,
-            config,
+            editor,
+            history,
+            image,
+            glyphs,
+            regions,
             ..
         } = self;
 
         use graphics::*;
 
         const BACKGROUND: [f32; 4] = [0.0, 0.0, 0.0, 0.0];
-        const TRANSLUCENT_WHITE: [f32; 4] = [1.0, 1.0, 1.0, 0.2];
-        const BLACK: [f32; 4] = [0.0, 0.0, 0.0, 1.0];
+        const MASK: [f32; 4] = [0.0, 0.0, 0.0, 0.5];
 
-        let (window_width, window_height) = (args.window_size[0], args.window_size[1]);
-        let (x, y) = (args.window_size[0] / 2.0, args.window_size[1] / 2.0);
+        let trans = editor.view().transform();
+        let crop = history.crop();
 
-        let (image_width, image_height) = texture.get_size();
+        // geometry in source pixels, like it is printed by `--select-only`
+        let readout = editor
+            .selection_rect()
+            .map(|rect| rect.offset(crop.x, crop.y).to_string())
+            .or_else(|| {
+                editor
+                    .measurement()
+                    .map(|(from, to)| editor::describe_distance(from, to))
+            });
 
-        let (ratio_width, ratio_height) = (
-            window_width / image_width as f64,
-            window_height / image_height as f64,
-        );
-
-        let ratio =
-            f64::min(ratio_width, ratio_height) * if config.force_fullscreen { 1.0 } else { 0.95 };
-
-        let trans = (mat2x3_id() as Matrix2d)
-            .trans(x, y)
-            .scale(ratio, ratio)
-            .trans(
-                0.0 - (image_width / 2) as f64,
-                0.0 - (image_height / 2) as f64,
-            );
+        let loupe = match (editor.loupe(), editor.mouse(), editor.pixel()) {
+            (true, Some(mouse), Some((x, y))) => Some((mouse, (x, y))),
+            _ => None,
+        };
 
         gl.draw(args.viewport(), |ctx, gl| {
             // Clear the screen.
@@ -92,154 +260,494 @@ impl App {
 
             let trans = ctx.transform.append_transform(trans);
 
-            // Draw a box rotating around the middle of the screen.
             graphics::image(texture, trans, gl);
 
-            // draw selection box
-            if let (Some(start), Some(end)) = (area_selection.0, last_mouse_pos) {
-                let a = start;
-                let c = *end;
-                let b = [c[0], a[1]];
-                let d = [a[0], c[1]];
-
-                graphics::line_from_to(BLACK, 0.5, a, b, ctx.transform, gl);
-                graphics::line_from_to(BLACK, 0.5, b, c, ctx.transform, gl);
-                graphics::line_from_to(BLACK, 0.5, c, d, ctx.transform, gl);
-                graphics::line_from_to(BLACK, 0.5, d, a, ctx.transform, gl);
-                graphics::rectangle_from_to(TRANSLUCENT_WHITE, a, c, ctx.transform, gl);
+            let origin = [-(crop.x as f64), -(crop.y as f64)];
+            for annotation in history.overlay() {
+                draw_annotation(&annotation.translate(origin), trans, glyphs, gl);
             }
-        });
 
-        if let (Some(start), Some(end)) = self.area_selection {
-            let (a, b) = {
-                (
-                    row_mat2x3_transform_pos2(mat2x3_inv(trans), start),
-                    row_mat2x3_transform_pos2(mat2x3_inv(trans), end),
-                )
-            };
-
-            // sanitize
-            let (a, b) = {
-                use std::cmp::min;
-                (
-                    (
-                        min(image_width, f64::max(0.0, a[0]) as u32),
-                        min(image_height, f64::max(0.0, a[1]) as u32),
-                    ),
-                    (
-                        min(image_width, f64::max(0.0, b[0]) as u32),
-                        min(image_height, f64::max(0.0, b[1]) as u32),
-                    ),
-                )
-            };
+            if let Some(annotation) = editor.preview() {
+                draw_annotation(&annotation, trans, glyphs, gl);
+            }
 
-            let (start, size) = {
-                use std::cmp::min;
+            // queued regions, numbered in the order they are saved
+            for (i, (_, region)) in regions.iter().enumerate() {
+                let rect = match region {
+                    Edit::Clip(clip) => clip.bounds(),
+                    Edit::Crop(rect) => *rect,
+                    _ => continue,
+                };
 
-                let start = (min(a.0, b.0), min(a.1, b.1));
+                let (x, y) = (rect.x as f64 - crop.x as f64, rect.y as f64 - crop.y as f64);
+                let a = editor.view().to_window([x, y]);
+                let c = editor
+                    .view()
+                    .to_window([x + rect.width as f64, y + rect.height as f64]);
 
-                // u32 abs() when?
-                let size = (
-                    a.0.checked_sub(b.0)
-                        .unwrap_or_else(|| b.0.checked_sub(a.0).unwrap()),
-                    b.1.checked_sub(a.1)
-                        .unwrap_or_else(|| a.1.checked_sub(b.1).unwrap()),
-                );
+                let outline = [a[0], a[1], c[0] - a[0], c[1] - a[1]];
+                Rectangle::new_border(BLACK, 1.5).draw(outline, &ctx.draw_state, ctx.transform, gl);
+                Rectangle::new_border(WHITE, 0.5).draw(outline, &ctx.draw_state, ctx.transform, gl);
 
-                (start, size)
-            };
+                draw_label(&(i + 1).to_string(), a, glyphs, ctx.transform, gl);
+            }
 
-            info!("Crop: {:#?}", (start, size));
+            // draw selection box
+            if let Some((a, c)) = editor.selection() {
+                let [width, height] = args.window_size;
+                let (x0, y0) = (f64::min(a[0], c[0]), f64::min(a[1], c[1]));
+                let (x1, y1) = (f64::max(a[0], c[0]), f64::max(a[1], c[1]));
 
-            self.image =
-                imageops::crop_imm(&self.image, start.0, start.1, size.0, size.1).to_image();
+                // darken everything that would be cropped away
+                for mask in [
+                    [0.0, 0.0, width, y0],
+                    [0.0, y1, width, height - y1],
+                    [0.0, y0, x0, y1 - y0],
+                    [x1, y0, width - x1, y1 - y0],
+                ] {
+                    rectangle(MASK, mask, ctx.transform, gl);
+                }
 
-            self.load_texture();
+                // a white line on a black one is visible on any background
+                let outline = [x0, y0, x1 - x0, y1 - y0];
+                Rectangle::new_border(BLACK, 1.5).draw(outline, &ctx.draw_state, ctx.transform, gl);
+                Rectangle::new_border(WHITE, 0.5).draw(outline, &ctx.draw_state, ctx.transform, gl);
 
-            self.area_selection = (None, None);
-        }
-    }
-
-    fn input(
-        &mut self,
-        window: &mut GlutinWindow,
-        button: Option<ButtonArgs>,
-        mouse: Option<[f64; 2]>,
-    ) {
-        if let Some(b) = button {
-            match b {
-                ButtonArgs {
-                    state: ButtonState::Press,
-                    button: Button::Mouse(MouseButton::Left),
-                    ..
-                } => {
-                    if let Some(mouse) = self.last_mouse_pos {
-                        self.area_selection.0 = Some(mouse);
-                        self.area_selection.1 = None;
-                    }
-                }
-                ButtonArgs {
-                    state: ButtonState::Release,
-                    button: Button::Mouse(MouseButton::Left),
-                    ..
-                } => {
-                    if let (Some(mouse), Some(_)) = (self.last_mouse_pos, self.area_selection.0) {
-                        self.area_selection.1 = Some(mouse);
+                // resize handles on the corners and edge centers
+                let mid = [(x0 + x1) / 2.0, (y0 + y1) / 2.0];
+                for x in [x0, mid[0], x1] {
+                    for y in [y0, mid[1], y1] {
+                        if [x, y] != mid {
+                            rectangle(
+                                BLACK,
+                                rectangle::centered_square(x, y, 4.0),
+                                ctx.transform,
+                                gl,
+                            );
+                            rectangle(
+                                WHITE,
+                                rectangle::centered_square(x, y, 3.0),
+                                ctx.transform,
+                                gl,
+                            );
+                        }
                     }
                 }
-                ButtonArgs {
-                    state: ButtonState::Release,
-                    button: Button::Keyboard(Key::W),
-                    ..
-                } => {
-                    if self.area_selection.0.is_some() {
-                        self.area_selection = (None, None);
-                    } else {
-                        info!("saving image..");
-                        let _ = self
-                            .config
-                            .save_image(DynamicImage::ImageRgba8(self.image.clone()))
-                            .map_err(|e| error!("Error while saving image: {:#?}", e));
-
-                        window.set_should_close(true);
-                    }
+            }
+
+            // the ellipse inside the selection
+            if let (Tool::CropEllipse, Some((a, c))) = (editor.tool(), editor.selection()) {
+                let outline = [
+                    f64::min(a[0], c[0]),
+                    f64::min(a[1], c[1]),
+                    (a[0] - c[0]).abs(),
+                    (a[1] - c[1]).abs(),
+                ];
+
+                Ellipse::new_border(BLACK, 1.5).draw(outline, &ctx.draw_state, ctx.transform, gl);
+                Ellipse::new_border(WHITE, 0.5).draw(outline, &ctx.draw_state, ctx.transform, gl);
+            }
+
+            if let Some(lasso) = editor.lasso() {
+                // closed back to the start, like the crop will be
+                let segments = lasso.iter().zip(lasso.iter().cycle().skip(1));
+
+                for (a, b) in segments {
+                    let segment = [a[0], a[1], b[0], b[1]];
+
+                    Line::new(BLACK, 1.5).draw(segment, &ctx.draw_state, ctx.transform, gl);
+                    Line::new(WHITE, 0.5).draw(segment, &ctx.draw_state, ctx.transform, gl);
                 }
-                ButtonArgs {
-                    state: ButtonState::Release,
-                    button: Button::Keyboard(Key::Q),
-                    ..
+            }
+
+            if let Some((mouse, (x, y))) = loupe {
+                let label = format!(
+                    "{}, {}  {}",
+                    x + crop.x,
+                    y + crop.y,
+                    color::to_hex(*image.get_pixel(x, y))
+                );
+
+                draw_loupe(image, (x, y), mouse, &label, glyphs, &ctx, gl);
+            }
+
+            if let (Some(rect), Some([x, y])) = (editor.selection_rect(), editor.mouse()) {
+                let size = format!("{}x{}", rect.width, rect.height);
+                draw_label(&size, [x + 16.0, y + 16.0], glyphs, ctx.transform, gl);
+            }
+
+            if let Some(readout) = readout {
+                let [_, height] = args.window_size;
+                draw_label(
+                    &readout,
+                    [0.0, height - LABEL_HEIGHT],
+                    glyphs,
+                    ctx.transform,
+                    gl,
+                );
+            }
+        });
+    }
+
+    fn input<E: GenericEvent>(&mut self, window: &mut GlutinWindow, e: &E) {
+        match self.editor.event(e) {
+            Some(Command::Edit(edit @ Edit::Crop(_)))
+            | Some(Command::Edit(edit @ Edit::Clip(_)))
+                if self.config.select_only.is_some() =>
+            {
+                let crop = self.history.crop();
+                let rect = match edit {
+                    Edit::Clip(clip) => clip.bounds(),
+                    Edit::Crop(rect) => rect,
+                    _ => unreachable!(),
                 }
-                | ButtonArgs {
-                    state: ButtonState::Release,
-                    button: Button::Keyboard(Key::Escape),
-                    ..
-                } => {
-                    if self.area_selection.0.is_some() {
-                        self.area_selection.0 = None;
-                    } else {
-                        info!("Closing without saving..");
-
-                        window.set_should_close(true);
-                    }
+                .offset(crop.x, crop.y);
+
+                match self.config.select_only {
+                    Some(GeometryFormat::Json) => println!("{}", rect.to_json()),
+                    _ => println!("{}", rect),
                 }
-                _ => {}
+                self.selected = true;
+
+                window.set_should_close(true);
             }
-        }
+            Some(Command::Edit(edit @ Edit::Crop(_)))
+            | Some(Command::Edit(edit @ Edit::Clip(_)))
+                if self.config.regions.is_some() =>
+            {
+                self.queue_region(edit)
+            }
+            Some(Command::Edit(edit)) => self.apply(edit),
+            Some(Command::Undo) => self.undo(),
+            Some(Command::Redo) => self.redo(),
+            Some(Command::Pick(x, y)) => self.pick(x, y),
+            Some(Command::DeleteMarker(at)) => self.delete_marker(at),
+            Some(Command::Save) | Some(Command::Quit) if self.config.select_only.is_some() => {
+                warn!("closing without a selection..");
+
+                window.set_should_close(true);
+            }
+            Some(Command::Save) if !self.regions.is_empty() => {
+                info!("saving {} regions..", self.regions.len());
+                let _ = self
+                    .config
+                    .save_regions(self.regions())
+                    .map_err(|e| error!("Error while saving regions: {:#?}", e));
+
+                window.set_should_close(true);
+            }
+            Some(Command::Save) => {
+                info!("saving image..");
+                let _ = self
+                    .config
+                    .save_history(&self.history)
+                    .map_err(|e| error!("Error while saving image: {:#?}", e));
+
+                window.set_should_close(true);
+            }
+            Some(Command::Quit) => {
+                info!("Closing without saving..");
 
-        if let Some(m) = mouse {
-            self.last_mouse_pos = Some(m);
+                window.set_should_close(true);
+            }
+            None => {}
         }
     }
 
     fn update(&mut self, _args: &UpdateArgs) {}
 }
 
+fn describe_color(color: Rgba<u8>) -> String {
+    format!(
+        "{}  {}  {}",
+        color::to_hex(color),
+        color::to_rgb(color),
+        color::to_hsl(color)
+    )
+}
+
+/// Single pixels stay sharp when zooming in.
+fn image_texture_settings() -> TextureSettings {
+    TextureSettings::new().mag(Filter::Nearest)
+}
+
+const BLACK: [f32; 4] = [0.0, 0.0, 0.0, 1.0];
+const WHITE: [f32; 4] = [1.0, 1.0, 1.0, 1.0];
+
+const LABEL_SIZE: u32 = 14;
+const LABEL_HEIGHT: f64 = LABEL_SIZE as f64 + 12.0;
+
+/// White text on a black box with its top left corner at `pos`, in window coordinates.
+fn draw_label(
+    label: &str,
+    [x, y]: [f64; 2],
+    glyphs: &mut GlyphCache,
+    transform: Matrix2d,
+    gl: &mut GlGraphics,
+) {
+    let width = glyphs.width(LABEL_SIZE, label).unwrap_or(0.0) + 16.0;
+    graphics::rectangle(BLACK, [x, y, width, LABEL_HEIGHT], transform, gl);
+
+    let transform = transform.trans(x + 8.0, y + LABEL_HEIGHT - 8.0);
+    if let Err(e) = graphics::text(WHITE, LABEL_SIZE, label, glyphs, transform, gl) {
+        error!("failed to draw text: {:?}", e);
+    }
+}
+
+/// Pixels shown around the cursor in each direction by the magnifier.
+const LOUPE_RADIUS: i64 = 7;
+/// Size of a magnified image pixel in window pixels.
+const LOUPE_ZOOM: f64 = 8.0;
+
+/// A crosshair through `mouse` and a magnified view of the pixels around `(x, y)` with `label`
+/// below it.
+fn draw_loupe(
+    image: &RgbaImage,
+    (x, y): (u32, u32),
+    [mouse_x, mouse_y]: [f64; 2],
+    label: &str,
+    glyphs: &mut GlyphCache,
+    ctx: &Context,
+    gl: &mut GlGraphics,
+) {
+    use graphics::{line_from_to, rectangle, Rectangle};
+
+    let [width, height] = ctx.get_view_size();
+    let transform = ctx.transform;
+
+    const BACKDROP: [f32; 4] = [0.2, 0.2, 0.2, 1.0];
+    const GRID: [f32; 4] = [0.0, 0.0, 0.0, 0.25];
+
+    for (from, to) in [
+        ([0.0, mouse_y], [width, mouse_y]),
+        ([mouse_x, 0.0], [mouse_x, height]),
+    ] {
+        line_from_to(BLACK, 1.0, from, to, transform, gl);
+        line_from_to(WHITE, 0.5, from, to, transform, gl);
+    }
+
+    let cells = 2 * LOUPE_RADIUS + 1;
+    let side = cells as f64 * LOUPE_ZOOM;
+
+    // above and right of the cursor, unless that is outside of the window
+    let left = if mouse_x + 24.0 + side > width {
+        mouse_x - 24.0 - side
+    } else {
+        mouse_x + 24.0
+    };
+    let top = if mouse_y - 24.0 - side - LABEL_HEIGHT < 0.0 {
+        mouse_y + 24.0
+    } else {
+        mouse_y - 24.0 - side - LABEL_HEIGHT
+    };
+
+    rectangle(BACKDROP, [left, top, side, side], transform, gl);
+
+    for row in 0..cells {
+        for column in 0..cells {
+            let (px, py) = (
+                x as i64 + column - LOUPE_RADIUS,
+                y as i64 + row - LOUPE_RADIUS,
+            );
+
+            if px < 0 || py < 0 || px >= image.width() as i64 || py >= image.height() as i64 {
+                continue;
+            }
+
+            let cell = [
+                left + column as f64 * LOUPE_ZOOM,
+                top + row as f64 * LOUPE_ZOOM,
+                LOUPE_ZOOM,
+                LOUPE_ZOOM,
+            ];
+            let color = color::to_f32(*image.get_pixel(px as u32, py as u32));
+
+            rectangle(color, cell, transform, gl);
+        }
+    }
+
+    for i in 1..cells {
+        let offset = i as f64 * LOUPE_ZOOM;
+
+        line_from_to(
+            GRID,
+            0.25,
+            [left + offset, top],
+            [left + offset, top + side],
+            transform,
+            gl,
+        );
+        line_from_to(
+            GRID,
+            0.25,
+            [left, top + offset],
+            [left + side, top + offset],
+            transform,
+            gl,
+        );
+    }
+
+    let center = LOUPE_RADIUS as f64 * LOUPE_ZOOM;
+    let center = [left + center, top + center, LOUPE_ZOOM, LOUPE_ZOOM];
+    Rectangle::new_border(BLACK, 1.0).draw(center, &Default::default(), transform, gl);
+    Rectangle::new_border(WHITE, 0.5).draw(center, &Default::default(), transform, gl);
+
+    Rectangle::new_border(BLACK, 1.0).draw(
+        [left, top, side, side],
+        &Default::default(),
+        transform,
+        gl,
+    );
+
+    draw_label(label, [left, top + side], glyphs, transform, gl);
+}
+
+fn draw_annotation(
+    annotation: &Annotation,
+    transform: Matrix2d,
+    glyphs: &mut GlyphCache,
+    gl: &mut GlGraphics,
+) {
+    let color = color::to_f32(annotation.color());
+    let outline = annotation.outline();
+    let radius = annotation.width() / 2.0;
+
+    if annotation.mode() == Mode::Multiply {
+        draw_multiplied(&outline, radius, color, transform, gl);
+    } else {
+        for &[from, to] in &outline.lines {
+            graphics::Line::new_round(color, radius).draw_from_to(
+                from,
+                to,
+                &Default::default(),
+                transform,
+                gl,
+            );
+        }
+    }
+
+    for polygon in outline.polygons {
+        graphics::polygon(color, &polygon, transform, gl);
+    }
+
+    if let Some((at, text)) = annotation.text() {
+        // the glyph cache takes points and renders them at 1.333 pixels each
+        let size = (annotation.style.font_size / 1.333).round() as u32;
+        let transform = transform.trans(at[0], at[1]);
+        let color = color::to_f32(annotation.text_color());
+
+        if let Err(e) = graphics::text(color, size, &text, glyphs, transform, gl) {
+            error!("failed to draw text: {:?}", e);
+        }
+    }
+}
+
+/// Multiplies the area covered by the lines of `outline` with `color` exactly once, even where
+/// the lines overlap.
+fn draw_multiplied(
+    outline: &Outline,
+    radius: f64,
+    [r, g, b, a]: [f32; 4],
+    transform: Matrix2d,
+    gl: &mut GlGraphics,
+) {
+    use graphics::{
+        draw_state::{Blend, DrawState},
+        Graphics, Line, Rectangle,
+    };
+
+    // mark the covered area in the stencil buffer, then fill it in one go
+    gl.clear_stencil(0);
+
+    for [from, to] in &outline.lines {
+        Line::new_round(BLACK, radius).draw_from_to(
+            *from,
+            *to,
+            &DrawState::new_clip(),
+            transform,
+            gl,
+        );
+    }
+
+    let points = outline.lines.iter().flatten().copied().collect::<Vec<_>>();
+    let [min, max] = raster::bounds(&points, radius + 1.0);
+
+    // blending multiplies with the colour as is, so the alpha has to be mixed in already
+    let color = [r, g, b].map(|c| 1.0 - a * (1.0 - c));
+
+    Rectangle::new([color[0], color[1], color[2], 1.0]).draw(
+        [min[0], min[1], max[0] - min[0], max[1] - min[1]],
+        &DrawState::new_inside().blend(Blend::Multiply),
+        transform,
+        gl,
+    );
+}
+
+/// Where the image to edit comes from.
+#[derive(Debug)]
+enum Source {
+    File(PathBuf),
+    Stdin,
+    Screen(Capture),
+    Clipboard,
+}
+
+/// How `--select-only` prints the selected region.
+#[derive(Debug, Clone, Copy)]
+enum GeometryFormat {
+    /// `WxH+X+Y`
+    X11,
+    Json,
+}
+
+/// How `--regions` saves the queued regions.
+#[derive(Debug, Clone, Copy)]
+enum RegionOutput {
+    /// Every region in its own numbered file.
+    Separate,
+    /// All regions below each other in one image.
+    Stacked,
+}
+
+/// Transparent space between stacked regions, in pixels.
+const STACK_GAP: u32 = 16;
+
 #[derive(Debug)]
 struct Config {
-    input_file: Option<PathBuf>,
-    output_file: Option<PathBuf>,
+    source: Source,
+    output_file: Option<Template>,
+    encoding: Encoding,
     graphical: bool,
+    select_only: Option<GeometryFormat>,
+    regions: Option<RegionOutput>,
+    /// Copy the saved image to the clipboard.
+    clipboard: bool,
+    /// Run as the background process that serves the clipboard, see `Config::copy`.
+    serve_clipboard: bool,
     force_fullscreen: bool,
+    style: Style,
+    redaction: Redaction,
+    /// Apply redactions to the source image stored in projects.
+    bake_redactions: bool,
+    constraint: Option<Constraint>,
+    operations: Vec<Operation>,
+}
+
+/// Command line flags that transform the image, applied in the order they were given.
+const OPERATIONS: &[&str] = &["crop", "resize", "scale", "rotate", "flip"];
+
+fn operation_arg(
+    name: &'static str,
+    value_name: &'static str,
+    help: &'static str,
+) -> clap::Arg<'static, 'static> {
+    clap::Arg::with_name(name)
+        .long(name)
+        .value_name(value_name)
+        .multiple(true)
+        .number_of_values(1)
+        .validator(move |value| Operation::parse(name, &value).map(|_| ()))
+        .help(help)
 }
 
 impl Config {
@@ -253,14 +761,84 @@ impl Config {
                     .short("i")
                     .long("input")
                     .value_name("input_file")
-                    .help("input file name"),
+                    .help("input file name; a `.coral` project is opened with all of its edits"),
+            )
+            .arg(
+                clap::Arg::with_name("capture")
+                    .short("c")
+                    .long("capture")
+                    .takes_value(false)
+                    .conflicts_with("input_file")
+                    .help("capture the screen instead of reading an input file"),
+            )
+            .arg(
+                clap::Arg::with_name("paste")
+                    .long("paste")
+                    .takes_value(false)
+                    .conflicts_with_all(&["input_file", "capture", "delay", "monitor"])
+                    .help("read the image from the clipboard instead of an input file"),
+            )
+            .arg(
+                clap::Arg::with_name("delay")
+                    .long("delay")
+                    .value_name("seconds")
+                    .conflicts_with("input_file")
+                    .validator(|s| parse_delay(&s).map(|_| ()))
+                    .help("wait before capturing the screen; implies `--capture`"),
+            )
+            .arg(
+                clap::Arg::with_name("monitor")
+                    .long("monitor")
+                    .value_name("index")
+                    .conflicts_with("input_file")
+                    .validator(|s| s.parse::<usize>().map(|_| ()).map_err(|e| e.to_string()))
+                    .help("only capture the n-th monitor as listed by `xrandr --listmonitors`; implies `--capture`"),
             )
             .arg(
                 clap::Arg::with_name("output_file")
                     .short("o")
                     .long("output")
                     .value_name("output_file")
-                    .help("output file name; if not specified, the image will be printed to `stdout`"),
+                    .help("output file name or directory; if not specified, the image will be printed to `stdout`. \
+                           `strftime` fields like `%Y-%m-%d`, `{w}`/`{h}` for the image size and a `{n}` counter \
+                           that never overwrites existing files are replaced. A `.coral` file saves a project that keeps the source image and every edit"),
+            )
+            .arg(
+                clap::Arg::with_name("output_dir")
+                    .long("output-dir")
+                    .value_name("directory")
+                    .help("directory for relative output file names; if no output file is given, the image is saved there under a default name"),
+            )
+            .arg(
+                clap::Arg::with_name("format")
+                    .long("format")
+                    .value_name("png|jpeg|gif|bmp|tiff|ppm|pgm|pbm|pam|tga|ico|farbfeld|svg")
+                    .validator(|s| s.parse::<Format>().map(|_| ()))
+                    .help("output format; defaults to the extension of `output_file` or png for `stdout`"),
+            )
+            .arg(
+                clap::Arg::with_name("quality")
+                    .long("quality")
+                    .value_name("1-100")
+                    .validator(|s| match s.parse::<u8>() {
+                        Ok(quality) if (1..=100).contains(&quality) => Ok(()),
+                        _ => Err(format!("quality has to be between 1 and 100, got `{}`", s)),
+                    })
+                    .help("jpeg quality"),
+            )
+            .arg(
+                clap::Arg::with_name("png_compression")
+                    .long("png-compression")
+                    .value_name("default|fast|best|huffman|rle")
+                    .validator(|s| output::parse_compression(&s).map(|_| ()))
+                    .help("png compression level"),
+            )
+            .arg(
+                clap::Arg::with_name("png_filter")
+                    .long("png-filter")
+                    .value_name("none|sub|up|avg|paeth")
+                    .validator(|s| output::parse_filter(&s).map(|_| ()))
+                    .help("png filter type"),
             )
             .arg(
                 clap::Arg::with_name("quiet")
@@ -269,6 +847,21 @@ impl Config {
                     .takes_value(false)
                     .help("silent execution"),
             )
+            .arg(
+                clap::Arg::with_name("clipboard")
+                    .long("clipboard")
+                    .takes_value(false)
+                    .help("copy the saved image to the clipboard as png; without `output_file` nothing is printed to `stdout`. \
+                           Separately saved `--regions` are copied stacked into one image. \
+                           A background process keeps serving the clipboard until something else is copied"),
+            )
+            .arg(
+                clap::Arg::with_name("serve_clipboard")
+                    .long("serve-clipboard")
+                    .takes_value(false)
+                    .hidden(true)
+                    .help("serve the png image read from `stdin` on the clipboard"),
+            )
             .arg(
                 clap::Arg::with_name("fullscreen")
                     .short("f")
@@ -283,24 +876,264 @@ impl Config {
                     .long("graphical")
                     .takes_value(false)
                     .help("Enables GUI to edit image; if omitted the default behaviour is to write `input_file` to `output_file`"),
-            ).get_matches();
+            )
+            .arg(
+                clap::Arg::with_name("select_only")
+                    .long("select-only")
+                    .takes_value(false)
+                    .help("only select a region and print its geometry as `WxH+X+Y` instead of cropping and saving the image; implies `--graphical`, exits with an error if nothing was selected"),
+            )
+            .arg(
+                clap::Arg::with_name("json")
+                    .long("json")
+                    .takes_value(false)
+                    .requires("select_only")
+                    .help("print the selected region as JSON"),
+            )
+            .arg(
+                clap::Arg::with_name("regions")
+                    .long("regions")
+                    .takes_value(false)
+                    .conflicts_with("select_only")
+                    .help("queue every confirmed crop as a region instead of cropping the image; on save each region is written to its own numbered file; implies `--graphical`"),
+            )
+            .arg(
+                clap::Arg::with_name("stack")
+                    .long("stack")
+                    .takes_value(false)
+                    .requires("regions")
+                    .help("save the regions below each other in a single image"),
+            )
+            .arg(
+                clap::Arg::with_name("color")
+                    .long("color")
+                    .value_name("hex")
+                    .validator(|s| color::parse(&s).map(|_| ()))
+                    .help("initial annotation colour, e.g. `#ff0000`"),
+            )
+            .arg(
+                clap::Arg::with_name("stroke_width")
+                    .long("stroke-width")
+                    .value_name("pixels")
+                    .validator(|s| match s.parse::<f64>() {
+                        Ok(width) if width > 0.0 && width.is_finite() => Ok(()),
+                        _ => Err(format!("`{}` is not a positive width", s)),
+                    })
+                    .help("initial annotation stroke width in image pixels"),
+            )
+            .arg(
+                clap::Arg::with_name("font_size")
+                    .long("font-size")
+                    .value_name("pixels")
+                    .validator(|s| match s.parse::<f64>() {
+                        Ok(size) if size > 0.0 && size.is_finite() => Ok(()),
+                        _ => Err(format!("`{}` is not a positive size", s)),
+                    })
+                    .help("initial text size in image pixels"),
+            )
+            .arg(
+                clap::Arg::with_name("redaction")
+                    .long("redaction")
+                    .value_name("fill|pixelate|blur")
+                    .validator(|s| s.parse::<Redaction>().map(|_| ()))
+                    .help("initial method of the redaction tool; `fill` is the only one that can not be reversed"),
+            )
+            .arg(
+                clap::Arg::with_name("bake_redactions")
+                    .long("bake-redactions")
+                    .takes_value(false)
+                    .help("store the source image of a `.coral` project with the redactions applied, otherwise the project still contains the redacted pixels"),
+            )
+            .arg(
+                clap::Arg::with_name("constraint")
+                    .long("constraint")
+                    .value_name("W:H|WxH")
+                    .validator(|s| s.parse::<Constraint>().map(|_| ()))
+                    .help("lock selections to an aspect ratio like `16:9` or a size like `1280x720` in image pixels; holding shift while dragging keeps them square"),
+            )
+            .arg(operation_arg("crop", "WxH+X+Y", "crop the image to the given geometry"))
+            .arg(operation_arg("resize", "WxH", "resize the image; if `W` or `H` is omitted the aspect ratio is kept"))
+            .arg(operation_arg("scale", "factor", "scale the image by a percentage like `50%` or a factor like `0.5`"))
+            .arg(operation_arg("rotate", "90|180|270", "rotate the image clockwise"))
+            .arg(operation_arg("flip", "h|v", "flip the image horizontally or vertically"))
+            .get_matches();
 
         if !matches.is_present("quiet") {
             simple_logger::SimpleLogger::new().init().unwrap();
         }
 
+        let output_dir = matches.value_of("output_dir").map(Path::new);
+        let output_file = match (matches.value_of("output_file"), output_dir) {
+            (Some(output), dir) => Some(Template::new(output, dir)),
+            (None, Some(dir)) => Some(Template::in_dir(dir)),
+            (None, None) => None,
+        };
+        let encoding = Self::encoding(&matches);
+
+        // fail before the image is edited, not when saving it; projects are not encoded
+        let path = output_file.as_ref().map(Template::path);
+        let format = match path {
+            Some(path) if project::is_project(path) => None,
+            path => match encoding.format_for(path) {
+                Ok(format) => Some(format),
+                Err(e) => {
+                    clap::Error::with_description(&e, clap::ErrorKind::ArgumentConflict).exit()
+                }
+            },
+        };
+
+        // encoder settings for another format would be ignored without a word
+        for (name, applies_to) in [
+            ("quality", Format::Jpeg),
+            ("png_compression", Format::Png),
+            ("png_filter", Format::Png),
+        ] {
+            // an svg embeds a png
+            let applies = format == Some(applies_to)
+                || (applies_to == Format::Png && format == Some(Format::Svg));
+
+            if matches.is_present(name) && !applies {
+                let e = format!(
+                    "`--{}` only applies to {:?}, the output is {}",
+                    name.replace('_', "-"),
+                    applies_to,
+                    format.map_or("a project".to_owned(), |format| format!("{:?}", format))
+                );
+                clap::Error::with_description(&e, clap::ErrorKind::ArgumentConflict).exit();
+            }
+        }
+
         Self {
-            input_file: matches.value_of("input_file").map(|s| s.into()),
-            output_file: matches.value_of("output_file").map(|s| s.into()),
-            graphical: matches.is_present("gui"),
+            source: Self::source(&matches),
+            output_file,
+            encoding,
+            graphical: ["gui", "select_only", "regions"]
+                .iter()
+                .any(|name| matches.is_present(name)),
+            select_only: match (
+                matches.is_present("select_only"),
+                matches.is_present("json"),
+            ) {
+                (false, _) => None,
+                (true, false) => Some(GeometryFormat::X11),
+                (true, true) => Some(GeometryFormat::Json),
+            },
+            regions: match (matches.is_present("regions"), matches.is_present("stack")) {
+                (false, _) => None,
+                (true, false) => Some(RegionOutput::Separate),
+                (true, true) => Some(RegionOutput::Stacked),
+            },
+            clipboard: matches.is_present("clipboard"),
+            serve_clipboard: matches.is_present("serve_clipboard"),
             force_fullscreen: matches.is_present("fullscreen"),
+            style: Self::style(&matches),
+            // values were already checked by the argument's validator
+            redaction: matches
+                .value_of("redaction")
+                .map(|s| s.parse().unwrap())
+                .unwrap_or_default(),
+            bake_redactions: matches.is_present("bake_redactions"),
+            constraint: matches.value_of("constraint").map(|s| s.parse().unwrap()),
+            operations: Self::operations(&matches),
+        }
+    }
+
+    fn source(matches: &clap::ArgMatches) -> Source {
+        if let Some(path) = matches.value_of("input_file") {
+            Source::File(path.into())
+        } else if matches.is_present("paste") {
+            Source::Clipboard
+        } else if ["capture", "delay", "monitor"]
+            .iter()
+            .any(|name| matches.is_present(name))
+        {
+            // values were already checked by the argument's validator
+            Source::Screen(Capture {
+                monitor: matches.value_of("monitor").map(|n| n.parse().unwrap()),
+                delay: matches
+                    .value_of("delay")
+                    .map(|s| parse_delay(s).unwrap())
+                    .unwrap_or_default(),
+            })
+        } else {
+            Source::Stdin
+        }
+    }
+
+    fn encoding(matches: &clap::ArgMatches) -> Encoding {
+        let default = Encoding::default();
+
+        // values were already checked by the argument's validator
+        Encoding {
+            format: matches.value_of("format").map(|s| s.parse().unwrap()),
+            quality: matches
+                .value_of("quality")
+                .map(|s| s.parse().unwrap())
+                .unwrap_or(default.quality),
+            compression: matches
+                .value_of("png_compression")
+                .map(|s| output::parse_compression(s).unwrap())
+                .unwrap_or(default.compression),
+            filter: matches
+                .value_of("png_filter")
+                .map(|s| output::parse_filter(s).unwrap())
+                .unwrap_or(default.filter),
+        }
+    }
+
+    fn style(matches: &clap::ArgMatches) -> Style {
+        let default = Style::default();
+
+        // values were already checked by the argument's validator
+        Style {
+            color: matches
+                .value_of("color")
+                .map(|s| color::parse(s).unwrap())
+                .unwrap_or(default.color),
+            width: matches
+                .value_of("stroke_width")
+                .map(|s| s.parse().unwrap())
+                .unwrap_or(default.width),
+            font_size: matches
+                .value_of("font_size")
+                .map(|s| s.parse().unwrap())
+                .unwrap_or(default.font_size),
+        }
+    }
+
+    fn operations(matches: &clap::ArgMatches) -> Vec<Operation> {
+        let mut operations = OPERATIONS
+            .iter()
+            .flat_map(|name| {
+                matches
+                    .indices_of(name)
+                    .into_iter()
+                    .flatten()
+                    .zip(matches.values_of(name).into_iter().flatten())
+                    // values were already checked by the argument's validator
+                    .map(move |(index, value)| (index, Operation::parse(name, value).unwrap()))
+            })
+            .collect::<Vec<_>>();
+
+        operations.sort_by_key(|(index, _)| *index);
+        operations.into_iter().map(|(_, op)| op).collect()
+    }
+
+    /// The image to edit, a project file is opened with all of its edits.
+    fn open(&self) -> ImageResult<History> {
+        match &self.source {
+            Source::File(path) if project::is_project(path) => project::open(path),
+            _ => self.open_image().map(History::new),
         }
     }
 
     fn open_image(&self) -> ImageResult<RgbaImage> {
-        match &self.input_file {
-            Some(path) => Ok(image::io::Reader::open(&path)?.decode()?.to_rgba8()),
-            None => {
+        match &self.source {
+            Source::File(path) => Ok(image::io::Reader::open(&path)?.decode()?.to_rgba8()),
+            Source::Screen(capture) => capture
+                .capture()
+                .map_err(|e| ImageError::IoError(io::Error::new(io::ErrorKind::Other, e))),
+            Source::Stdin => {
                 info!("reading image data from stdin..");
 
                 let stdin = std::io::stdin();
@@ -312,20 +1145,205 @@ impl Config {
                     .decode()?
                     .to_rgba8())
             }
+            Source::Clipboard => {
+                info!("reading image data from the clipboard..");
+
+                let buf = clipboard::paste()
+                    .map_err(|e| ImageError::IoError(io::Error::new(io::ErrorKind::Other, e)))?;
+
+                Ok(image::load_from_memory(&buf)?.to_rgba8())
+            }
         }
     }
 
-    fn save_image(&self, image: DynamicImage) -> ImageResult<()> {
+    /// Saves the edited image, or the source image and its edits if the output is a project.
+    /// An SVG keeps the annotations as vector shapes.
+    fn save_history(&self, history: &History) -> ImageResult<()> {
+        let crop = history.crop();
+
         match &self.output_file {
-            Some(path) => {
-                info!("saving as {}", path.to_string_lossy());
-                image.save(path)?;
+            Some(template) if project::is_project(template.path()) => {
+                if self.clipboard {
+                    self.copy(&DynamicImage::ImageRgba8(history.flatten()))?;
+                }
+
+                let (path, file) =
+                    template.create(&chrono::Local::now(), (crop.width, crop.height))?;
+
+                info!("saving project as {}", path.to_string_lossy());
+
+                if self.bake_redactions {
+                    return project::save(&history.bake_redactions(), file);
+                }
+
+                if history
+                    .edits()
+                    .iter()
+                    .any(|edit| matches!(edit, Edit::Redact(..)))
+                {
+                    warn!("the project keeps the redacted pixels in its source image, use `--bake-redactions` to remove them");
+                }
+
+                project::save(history, file)
+            }
+            output_file => {
+                let format = self
+                    .encoding
+                    .format_for(output_file.as_ref().map(Template::path))
+                    .map_err(parameter_error)?;
+
+                if format != Format::Svg {
+                    return self.save_image(DynamicImage::ImageRgba8(history.flatten()));
+                }
+
+                if self.clipboard {
+                    self.copy(&DynamicImage::ImageRgba8(history.flatten()))?;
+
+                    if output_file.is_none() {
+                        return Ok(());
+                    }
+                }
+
+                // annotations are written as vector shapes instead of burning them in
+                let png = self
+                    .encoding
+                    .encode(&DynamicImage::ImageRgba8(history.render()), Format::Png)?;
+                let svg = svg::document(crop, &png, &history.overlay(), &history.clips());
+
+                self.write_output(
+                    svg.as_bytes(),
+                    (crop.width, crop.height),
+                    format,
+                    output_file.as_ref(),
+                )
+            }
+        }
+    }
+
+    fn save_regions(&self, regions: Vec<RgbaImage>) -> ImageResult<()> {
+        if let Some(RegionOutput::Separate) = self.regions {
+            match &self.output_file {
+                Some(template) => {
+                    let template = template.numbered();
+
+                    // a single clipboard can not hold every region
+                    if self.clipboard {
+                        self.copy(&DynamicImage::ImageRgba8(ops::stack(&regions, STACK_GAP)))?;
+                    }
+
+                    for region in regions {
+                        self.save_image_as(DynamicImage::ImageRgba8(region), Some(&template))?;
+                    }
+
+                    return Ok(());
+                }
+                None => warn!("only one image can be written to stdout, stacking the regions.."),
+            }
+        }
+
+        self.save_image(DynamicImage::ImageRgba8(ops::stack(&regions, STACK_GAP)))
+    }
+
+    /// Whether the image goes to `stdout`: it is neither saved to a file nor copied, and no
+    /// selection is printed instead.
+    fn prints_image(&self) -> bool {
+        self.output_file.is_none() && !self.clipboard && self.select_only.is_none()
+    }
+
+    fn save_image(&self, image: DynamicImage) -> ImageResult<()> {
+        if self.clipboard {
+            self.copy(&image)?;
+
+            if self.output_file.is_none() {
+                return Ok(());
+            }
+        }
+
+        self.save_image_as(image, self.output_file.as_ref())
+    }
+
+    /// Encodes `image` into the file named by `output_file`, or to stdout if there is none.
+    fn save_image_as(
+        &self,
+        image: DynamicImage,
+        output_file: Option<&Template>,
+    ) -> ImageResult<()> {
+        let format = self
+            .encoding
+            .format_for(output_file.map(Template::path))
+            .map_err(parameter_error)?;
+
+        if !format.keeps_alpha() && image.pixels().any(|(_, _, pixel)| pixel[3] < 255) {
+            warn!(
+                "{:?} has no transparency, transparent pixels will be saved opaque..",
+                format
+            );
+        }
+
+        self.write_output(
+            &self.encoding.encode(&image, format)?,
+            image.dimensions(),
+            format,
+            output_file,
+        )
+    }
+
+    /// X11 does not keep the clipboard after we exit, so a copy of this program is started in
+    /// the background to serve `image` until something else is copied.
+    fn copy(&self, image: &DynamicImage) -> ImageResult<()> {
+        let png = self.encoding.encode(image, Format::Png)?;
+
+        // in its own process group the server outlives the terminal we were started from
+        let mut server = process::Command::new(env::current_exe()?)
+            .args(&["--serve-clipboard", "--quiet"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .process_group(0)
+            .spawn()?;
+
+        // closing stdin tells the server that the image is complete
+        server
+            .stdin
+            .take()
+            .expect("stdin is piped")
+            .write_all(&png)?;
+
+        // the server prints a line once it owns the clipboard
+        let mut ready = String::new();
+        BufReader::new(server.stdout.take().expect("stdout is piped")).read_line(&mut ready)?;
+
+        if ready.is_empty() {
+            return Err(ImageError::IoError(io::Error::new(
+                io::ErrorKind::Other,
+                "the clipboard server exited early",
+            )));
+        }
+
+        info!("copied image to the clipboard");
+        Ok(())
+    }
+
+    /// Writes encoded image `data` into the file named by `output_file`, or to stdout if there
+    /// is none.
+    fn write_output(
+        &self,
+        data: &[u8],
+        dimensions: (u32, u32),
+        format: Format,
+        output_file: Option<&Template>,
+    ) -> ImageResult<()> {
+        match output_file {
+            Some(template) => {
+                let (path, mut file) = template.create(&chrono::Local::now(), dimensions)?;
+
+                info!("saving as {} ({:?})", path.to_string_lossy(), format);
+                file.write_all(data)?;
             }
             None => {
                 if !atty::is(atty::Stream::Stdout) {
                     let stdout = std::io::stdout();
 
-                    image.write_to(&mut stdout.lock(), ImageOutputFormat::Png)?;
+                    stdout.lock().write_all(data)?;
                 } else {
                     warn!("stdout is a tty, aborting printing binary..");
                 }
@@ -336,9 +1354,32 @@ impl Config {
     }
 }
 
-fn run_graphical(config: Config) {
+/// Seconds like `1.5`; too large values are rejected instead of overflowing the `Duration`.
+fn parse_delay(s: &str) -> std::result::Result<Duration, String> {
+    s.parse::<f64>()
+        .ok()
+        .and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+        .ok_or_else(|| format!("`{}` is not a number of seconds", s))
+}
+
+/// Unwraps I/O errors, so their message is printed without the `ImageError` around it.
+fn io_error(e: ImageError) -> io::Error {
+    match e {
+        ImageError::IoError(e) => e,
+        e => io::Error::new(io::ErrorKind::Other, e),
+    }
+}
+
+fn parameter_error(e: String) -> ImageError {
+    ImageError::Parameter(ParameterError::from_kind(ParameterErrorKind::Generic(e)))
+}
+
+fn run_graphical(config: Config) -> Result<()> {
     let opengl = OpenGL::V3_2;
 
+    // open the image first, a screen capture should not contain our own window
+    let history = config.open().map_err(io_error)?;
+
     // Create an Glutin window.
     let mut window = WindowSettings::new(std::env!("CARGO_BIN_NAME"), [200, 200])
         .graphics_api(opengl)
@@ -349,42 +1390,87 @@ fn run_graphical(config: Config) {
         .unwrap();
 
     // Create a new game and run it.
-    let mut app = App::new(GlGraphics::new(opengl), config);
+    let mut app = App::new(GlGraphics::new(opengl), config, history);
 
     let mut events = Events::new(EventSettings::new());
     while let Some(e) = events.next(&mut window) {
+        app.input(&mut window, &e);
+
         if let Some(args) = e.render_args() {
             app.render(&args);
         }
 
-        app.input(&mut window, e.button_args(), e.mouse_cursor_args());
-
         if let Some(args) = e.update_args() {
             app.update(&args);
         }
     }
+
+    app.print_picks();
+
+    // scripts using `--select-only` have to tell a selection apart from giving up
+    if app.config.select_only.is_some() && !app.selected {
+        return Err(io::Error::new(
+            io::ErrorKind::Other,
+            "closed without a selection",
+        ));
+    }
+
+    Ok(())
 }
 
-fn run_cli(config: Config) {
+fn run_cli(config: Config) -> Result<()> {
     info!("CLI runner.");
 
-    let image = config.open_image().unwrap();
+    let history = config.open().map_err(io_error)?;
+
+    // operations change the pixels, afterwards only the flat image is left to save
+    let history = if config.operations.is_empty() {
+        history
+    } else {
+        let image = config
+            .operations
+            .iter()
+            .try_fold(history.flatten(), |image, op| {
+                info!("{:?}", op);
+                op.apply(image)
+            })
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+
+        History::new(image)
+    };
 
-    let _ = config
-        .save_image(DynamicImage::ImageRgba8(image))
-        .map_err(|e| error!("Error while saving image: {:#?}", e));
+    config.save_history(&history).map_err(io_error)
 }
 
-fn main() -> Result<()> {
+/// The background process started by `Config::copy`.
+fn serve_clipboard() -> Result<()> {
+    let mut png = Vec::new();
+    io::stdin().lock().read_to_end(&mut png)?;
+
+    clipboard::serve(&png, || {
+        println!("ready");
+        let _ = io::stdout().flush();
+    })
+    .map_err(|e| io::Error::new(io::ErrorKind::Other, e))
+}
+
+fn main() {
     let config = Config::parse();
     debug!("config: {:#?}", config);
 
-    if config.graphical {
-        run_graphical(config);
+    let result = if config.serve_clipboard {
+        serve_clipboard()
+    } else if config.graphical {
+        run_graphical(config)
     } else {
-        run_cli(config);
+        run_cli(config)
+    };
+
+    // printed even with `--quiet`, the exit status alone does not tell what went wrong
+    if let Err(e) = result {
+        eprintln!("{}: {}", std::env!("CARGO_BIN_NAME"), e);
+        std::process::exit(1);
     }
 
     info!("exiting successfully");
-    Ok(())
 }
@@ -0,0 +1,243 @@
+use std::str::FromStr;
+
+use image::{imageops, RgbaImage};
+
+use crate::history::Rect;
+
+/// Image transformations that can be applied from the command line.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Operation {
+    Crop(Rect),
+    Resize(Option<u32>, Option<u32>),
+    Scale(f64),
+    Rotate(Rotation),
+    Flip(Axis),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rotation {
+    Cw90,
+    Cw180,
+    Cw270,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Axis {
+    Horizontal,
+    Vertical,
+}
+
+impl Operation {
+    /// Parses the value of the command line flag `name`, e.g. `--crop 200x100+10+20`.
+    pub fn parse(name: &str, value: &str) -> Result<Self, String> {
+        match name {
+            "crop" => value.parse().map(Operation::Crop),
+            "resize" => parse_size(value).map(|(w, h)| Operation::Resize(w, h)),
+            "scale" => parse_scale(value).map(Operation::Scale),
+            "rotate" => value.parse().map(Operation::Rotate),
+            "flip" => value.parse().map(Operation::Flip),
+            _ => Err(format!("unknown operation `{}`", name)),
+        }
+    }
+
+    /// Fails for a crop that does not fit into the image, instead of cropping to less.
+    pub fn apply(&self, image: RgbaImage) -> Result<RgbaImage, String> {
+        let (width, height) = image.dimensions();
+
+        Ok(match self {
+            Operation::Crop(rect) => {
+                if rect.intersect(&Rect::new(0, 0, width, height)) != *rect {
+                    return Err(format!(
+                        "the crop {} does not fit into the {}x{} image",
+                        rect, width, height
+                    ));
+                }
+
+                imageops::crop_imm(&image, rect.x, rect.y, rect.width, rect.height).to_image()
+            }
+            Operation::Resize(new_width, new_height) => {
+                // a missing side keeps the aspect ratio
+                let (new_width, new_height) = match (new_width, new_height) {
+                    (Some(w), Some(h)) => (*w, *h),
+                    (Some(w), None) => (*w, scale_side(height, *w as f64 / width as f64)),
+                    (None, Some(h)) => (scale_side(width, *h as f64 / height as f64), *h),
+                    (None, None) => (width, height),
+                };
+
+                imageops::resize(&image, new_width, new_height, imageops::Lanczos3)
+            }
+            Operation::Scale(factor) => imageops::resize(
+                &image,
+                scale_side(width, *factor),
+                scale_side(height, *factor),
+                imageops::Lanczos3,
+            ),
+            Operation::Rotate(Rotation::Cw90) => imageops::rotate90(&image),
+            Operation::Rotate(Rotation::Cw180) => imageops::rotate180(&image),
+            Operation::Rotate(Rotation::Cw270) => imageops::rotate270(&image),
+            Operation::Flip(Axis::Horizontal) => imageops::flip_horizontal(&image),
+            Operation::Flip(Axis::Vertical) => imageops::flip_vertical(&image),
+        })
+    }
+}
+
+/// Places the images below each other, left aligned and `gap` pixels apart, on a transparent
+/// background.
+pub fn stack(images: &[RgbaImage], gap: u32) -> RgbaImage {
+    let width = images.iter().map(RgbaImage::width).max().unwrap_or(0);
+    let height = images.iter().map(|image| image.height() + gap).sum::<u32>();
+
+    let mut stacked = RgbaImage::new(width, height.saturating_sub(gap));
+
+    let mut y = 0;
+    for image in images {
+        imageops::replace(&mut stacked, image, 0, y);
+        y += image.height() + gap;
+    }
+
+    stacked
+}
+
+fn scale_side(side: u32, factor: f64) -> u32 {
+    u32::max(1, (side as f64 * factor).round() as u32)
+}
+
+pub(crate) fn parse_u32(value: &str) -> Result<u32, String> {
+    value
+        .parse()
+        .map_err(|_| format!("`{}` is not a positive integer", value))
+}
+
+/// Parses `WxH`, where either side may be left out.
+pub(crate) fn parse_size(value: &str) -> Result<(Option<u32>, Option<u32>), String> {
+    let mut sides = value.splitn(2, 'x');
+
+    let mut side = || match sides.next() {
+        Some("") | None => Ok(None),
+        Some(s) => match parse_u32(s)? {
+            0 => Err(format!("`{}` has an empty side", value)),
+            side => Ok(Some(side)),
+        },
+    };
+
+    match (side()?, side()?) {
+        (None, None) => Err(format!("expected `WxH`, got `{}`", value)),
+        size => Ok(size),
+    }
+}
+
+/// Parses either a percentage like `50%` or a factor like `0.5`.
+fn parse_scale(value: &str) -> Result<f64, String> {
+    let factor = match value.strip_suffix('%') {
+        Some(percent) => percent.parse::<f64>().map(|p| p / 100.0),
+        None => value.parse(),
+    }
+    .map_err(|_| format!("`{}` is neither a percentage nor a factor", value))?;
+
+    if factor > 0.0 && factor.is_finite() {
+        Ok(factor)
+    } else {
+        Err(format!("scale has to be positive, got `{}`", value))
+    }
+}
+
+impl FromStr for Rotation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "90" => Ok(Rotation::Cw90),
+            "180" => Ok(Rotation::Cw180),
+            "270" => Ok(Rotation::Cw270),
+            _ => Err(format!("rotation has to be 90, 180 or 270, got `{}`", s)),
+        }
+    }
+}
+
+impl FromStr for Axis {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "h" | "horizontal" => Ok(Axis::Horizontal),
+            "v" | "vertical" => Ok(Axis::Vertical),
+            _ => Err(format!("flip has to be `h` or `v`, got `{}`", s)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::Rgba;
+
+    use super::*;
+
+    fn image(width: u32, height: u32) -> RgbaImage {
+        RgbaImage::from_pixel(width, height, Rgba([10, 20, 30, 255]))
+    }
+
+    #[test]
+    fn parses_sizes() {
+        let cases = [
+            ("200x100", Ok((Some(200), Some(100)))),
+            ("200x", Ok((Some(200), None))),
+            ("200", Ok((Some(200), None))),
+            ("x100", Ok((None, Some(100)))),
+            ("x", Err(())),
+            ("", Err(())),
+            ("0x100", Err(())),
+            ("-5x100", Err(())),
+            ("ax100", Err(())),
+        ];
+
+        for (value, expected) in cases {
+            assert_eq!(parse_size(value).map_err(|_| ()), expected, "{}", value);
+        }
+    }
+
+    #[test]
+    fn parses_scales() {
+        let cases = [
+            ("50%", Ok(0.5)),
+            ("0.5", Ok(0.5)),
+            ("200%", Ok(2.0)),
+            ("0", Err(())),
+            ("0%", Err(())),
+            ("-1", Err(())),
+            ("inf", Err(())),
+            ("x", Err(())),
+        ];
+
+        for (value, expected) in cases {
+            assert_eq!(parse_scale(value).map_err(|_| ()), expected, "{}", value);
+        }
+    }
+
+    #[test]
+    fn output_dimensions() {
+        let cases = [
+            (Operation::Crop(Rect::new(2, 3, 5, 4)), (5, 4)),
+            (Operation::Resize(Some(100), None), (100, 50)),
+            (Operation::Resize(None, Some(10)), (20, 10)),
+            (Operation::Resize(Some(30), Some(30)), (30, 30)),
+            (Operation::Scale(0.5), (20, 10)),
+            (Operation::Rotate(Rotation::Cw90), (20, 40)),
+            (Operation::Rotate(Rotation::Cw180), (40, 20)),
+            (Operation::Rotate(Rotation::Cw270), (20, 40)),
+            (Operation::Flip(Axis::Horizontal), (40, 20)),
+            (Operation::Flip(Axis::Vertical), (40, 20)),
+        ];
+
+        for (operation, expected) in cases {
+            let result = operation.apply(image(40, 20)).unwrap();
+            assert_eq!(result.dimensions(), expected, "{:?}", operation);
+        }
+    }
+
+    #[test]
+    fn rejects_crops_outside_of_the_image() {
+        for rect in [Rect::new(20, 20, 5, 5), Rect::new(5, 5, 10, 2)] {
+            assert!(Operation::Crop(rect).apply(image(10, 10)).is_err());
+        }
+    }
+}
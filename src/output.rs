@@ -0,0 +1,279 @@
+use std::{
+    io::{Cursor, Write},
+    path::Path,
+    str::FromStr,
+};
+
+use image::{
+    codecs::{
+        bmp::BmpEncoder,
+        farbfeld::FarbfeldEncoder,
+        gif::GifEncoder,
+        ico::IcoEncoder,
+        jpeg::JpegEncoder,
+        png::{CompressionType, FilterType, PngEncoder},
+        pnm::{PNMSubtype, PnmEncoder, SampleEncoding},
+        tga::TgaEncoder,
+        tiff::TiffEncoder,
+    },
+    DynamicImage, ImageEncoder, ImageResult,
+};
+
+use crate::{history::Rect, svg};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Png,
+    Jpeg,
+    Gif,
+    Bmp,
+    Tiff,
+    Ppm,
+    /// Grayscale.
+    Pgm,
+    /// Black and white.
+    Pbm,
+    /// RGBA, the only PNM format with transparency.
+    Pam,
+    Tga,
+    Ico,
+    Farbfeld,
+    /// The image embedded in an SVG, annotations stay vector shapes.
+    Svg,
+}
+
+impl Format {
+    pub fn from_extension(path: &Path) -> Option<Self> {
+        path.extension()?.to_str()?.parse().ok()
+    }
+
+    pub fn keeps_alpha(self) -> bool {
+        !matches!(self, Format::Jpeg | Format::Ppm | Format::Pgm | Format::Pbm)
+    }
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    /// Format names are the same as the file extensions.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "png" => Ok(Format::Png),
+            "jpg" | "jpeg" => Ok(Format::Jpeg),
+            "gif" => Ok(Format::Gif),
+            "bmp" => Ok(Format::Bmp),
+            "tif" | "tiff" => Ok(Format::Tiff),
+            "ppm" => Ok(Format::Ppm),
+            "pgm" => Ok(Format::Pgm),
+            "pbm" => Ok(Format::Pbm),
+            "pam" => Ok(Format::Pam),
+            "tga" => Ok(Format::Tga),
+            "ico" => Ok(Format::Ico),
+            "ff" | "farbfeld" => Ok(Format::Farbfeld),
+            "svg" => Ok(Format::Svg),
+            _ => Err(format!("unsupported format `{}`", s)),
+        }
+    }
+}
+
+pub fn parse_compression(s: &str) -> Result<CompressionType, String> {
+    match s {
+        "default" => Ok(CompressionType::Default),
+        "fast" => Ok(CompressionType::Fast),
+        "best" => Ok(CompressionType::Best),
+        "huffman" => Ok(CompressionType::Huffman),
+        "rle" => Ok(CompressionType::Rle),
+        _ => Err(format!(
+            "compression has to be default, fast, best, huffman or rle, got `{}`",
+            s
+        )),
+    }
+}
+
+pub fn parse_filter(s: &str) -> Result<FilterType, String> {
+    match s {
+        "none" => Ok(FilterType::NoFilter),
+        "sub" => Ok(FilterType::Sub),
+        "up" => Ok(FilterType::Up),
+        "avg" => Ok(FilterType::Avg),
+        "paeth" => Ok(FilterType::Paeth),
+        _ => Err(format!(
+            "filter has to be none, sub, up, avg or paeth, got `{}`",
+            s
+        )),
+    }
+}
+
+/// Output format and encoder settings.
+#[derive(Debug, Clone, Copy)]
+pub struct Encoding {
+    /// Explicitly requested format; if `None` it is derived from the file extension.
+    pub format: Option<Format>,
+    /// JPEG quality from 1 to 100.
+    pub quality: u8,
+    pub compression: CompressionType,
+    pub filter: FilterType,
+}
+
+impl Default for Encoding {
+    fn default() -> Self {
+        Self {
+            format: None,
+            quality: 90,
+            compression: CompressionType::Default,
+            filter: FilterType::Sub,
+        }
+    }
+}
+
+impl Encoding {
+    /// The format to write `path` in, or stdout if `path` is `None`; fails if the requested
+    /// format does not match the file extension.
+    pub fn format_for(&self, path: Option<&Path>) -> Result<Format, String> {
+        let extension = path.and_then(Format::from_extension);
+
+        match (self.format, extension) {
+            (Some(format), Some(extension)) if format != extension => Err(format!(
+                "format {:?} conflicts with the extension of `{}`",
+                format,
+                path.unwrap().to_string_lossy()
+            )),
+            (Some(format), _) | (None, Some(format)) => Ok(format),
+            (None, None) => match path {
+                Some(path) => Err(format!(
+                    "can not derive the format of `{}` from its extension, use `--format`",
+                    path.to_string_lossy()
+                )),
+                None => Ok(Format::Png),
+            },
+        }
+    }
+
+    pub fn encode(&self, image: &DynamicImage, format: Format) -> ImageResult<Vec<u8>> {
+        // tiff needs to seek, so everything is encoded in memory first
+        let mut buf = Cursor::new(Vec::new());
+
+        let rgba = image.to_rgba8();
+        let (width, height) = rgba.dimensions();
+        let color = image::ColorType::Rgba8;
+
+        match format {
+            Format::Png => PngEncoder::new_with_quality(&mut buf, self.compression, self.filter)
+                .write_image(&rgba, width, height, color)?,
+            Format::Jpeg => JpegEncoder::new_with_quality(&mut buf, self.quality).write_image(
+                &image.to_rgb8(),
+                width,
+                height,
+                image::ColorType::Rgb8,
+            )?,
+            Format::Gif => GifEncoder::new(&mut buf).encode(&rgba, width, height, color)?,
+            Format::Bmp => BmpEncoder::new(&mut buf).write_image(&rgba, width, height, color)?,
+            Format::Tiff => TiffEncoder::new(&mut buf).write_image(&rgba, width, height, color)?,
+            Format::Ppm => PnmEncoder::new(&mut buf)
+                .with_subtype(PNMSubtype::Pixmap(SampleEncoding::Binary))
+                .write_image(&image.to_rgb8(), width, height, image::ColorType::Rgb8)?,
+            Format::Pgm => PnmEncoder::new(&mut buf)
+                .with_subtype(PNMSubtype::Graymap(SampleEncoding::Binary))
+                .write_image(&image.to_luma8(), width, height, image::ColorType::L8)?,
+            Format::Pbm => {
+                // the encoder only writes black for 0, everything else would turn white
+                let mut luma = image.to_luma8();
+                for pixel in luma.pixels_mut() {
+                    pixel[0] = if pixel[0] < 0x80 { 0 } else { 0xff };
+                }
+
+                PnmEncoder::new(&mut buf)
+                    .with_subtype(PNMSubtype::Bitmap(SampleEncoding::Binary))
+                    .write_image(&luma, width, height, image::ColorType::L8)?
+            }
+            Format::Pam => PnmEncoder::new(&mut buf)
+                .with_subtype(PNMSubtype::ArbitraryMap)
+                .write_image(&rgba, width, height, color)?,
+            Format::Tga => TgaEncoder::new(&mut buf).write_image(&rgba, width, height, color)?,
+            Format::Ico => IcoEncoder::new(&mut buf).write_image(&rgba, width, height, color)?,
+            Format::Farbfeld => {
+                let rgba16 = DynamicImage::ImageRgba16(image.to_rgba16());
+
+                FarbfeldEncoder::new(&mut buf).write_image(
+                    rgba16.as_bytes(),
+                    width,
+                    height,
+                    image::ColorType::Rgba16,
+                )?
+            }
+            // a flat image has no annotations left, see `svg::document` for those
+            Format::Svg => {
+                let png = self.encode(image, Format::Png)?;
+                let svg = svg::document(Rect::new(0, 0, width, height), &png, &[], &[]);
+
+                buf.write_all(svg.as_bytes())?
+            }
+        }
+
+        Ok(buf.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{GenericImageView, ImageFormat, Rgba, RgbaImage};
+
+    use super::*;
+
+    #[test]
+    fn extensions_and_names_agree() {
+        for name in [
+            "png", "jpg", "jpeg", "gif", "bmp", "tif", "tiff", "ppm", "pgm", "pbm", "pam", "tga",
+            "ico", "ff", "farbfeld", "svg",
+        ] {
+            let path = format!("out.{}", name.to_uppercase());
+
+            assert_eq!(
+                Format::from_extension(Path::new(&path)),
+                name.parse().ok(),
+                "{}",
+                name
+            );
+            assert!(
+                Format::from_extension(Path::new(&path)).is_some(),
+                "{}",
+                name
+            );
+        }
+
+        assert_eq!(Format::from_extension(Path::new("out.webp")), None);
+    }
+
+    #[test]
+    fn encodes_every_format() {
+        let image = DynamicImage::ImageRgba8(RgbaImage::from_fn(4, 3, |x, y| {
+            Rgba([x as u8 * 60, y as u8 * 100, 200, 255])
+        }));
+
+        for (format, decoder) in [
+            (Format::Png, ImageFormat::Png),
+            (Format::Jpeg, ImageFormat::Jpeg),
+            (Format::Gif, ImageFormat::Gif),
+            (Format::Bmp, ImageFormat::Bmp),
+            (Format::Tiff, ImageFormat::Tiff),
+            (Format::Ppm, ImageFormat::Pnm),
+            (Format::Pgm, ImageFormat::Pnm),
+            (Format::Pbm, ImageFormat::Pnm),
+            (Format::Pam, ImageFormat::Pnm),
+            (Format::Tga, ImageFormat::Tga),
+            (Format::Ico, ImageFormat::Ico),
+            (Format::Farbfeld, ImageFormat::Farbfeld),
+        ] {
+            let data = Encoding::default().encode(&image, format).unwrap();
+
+            // the decoder can not read RGBA PAM files
+            if format == Format::Pam {
+                assert!(data.starts_with(b"P7\nWIDTH 4\nHEIGHT 3\n"));
+                continue;
+            }
+
+            let decoded = image::load_from_memory_with_format(&data, decoder).unwrap();
+            assert_eq!(decoded.dimensions(), (4, 3), "{:?}", format);
+        }
+    }
+}
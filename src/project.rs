@@ -0,0 +1,201 @@
+use std::{
+    fmt,
+    fs::File,
+    io::{self, Cursor, Read, Write},
+    path::Path,
+};
+
+use image::{DynamicImage, ImageError, ImageOutputFormat, ImageResult};
+use serde::{Deserialize, Serialize};
+use zip::{write::FileOptions, CompressionMethod, ZipArchive, ZipWriter};
+
+use crate::{
+    clip::Clip,
+    history::{Edit, History, Rect},
+};
+
+/// Extension of project files.
+pub const EXTENSION: &str = "coral";
+
+/// Version of the manifest, increased when old projects can no longer be read.
+const VERSION: u32 = 1;
+/// Archive entry with the untouched source image.
+const SOURCE: &str = "source.png";
+/// Archive entry with the [`Manifest`].
+const MANIFEST: &str = "project.json";
+
+/// Everything of a project except the source image.
+#[derive(Debug, Serialize, Deserialize)]
+struct Manifest {
+    version: u32,
+    /// Every crop, redaction and annotation in the order they were made, in source image
+    /// coordinates.
+    edits: Vec<Edit>,
+}
+
+pub fn is_project(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case(EXTENSION))
+}
+
+/// Reads a project saved with [`save`], it can be edited further from where it was left.
+/// Errors name the project file, they end up in front of the user.
+pub fn open(path: &Path) -> ImageResult<History> {
+    read(path).map_err(|e| {
+        let kind = match &e {
+            ImageError::IoError(e) => e.kind(),
+            _ => io::ErrorKind::InvalidData,
+        };
+
+        ImageError::IoError(io::Error::new(kind, format!("{}: {}", path.display(), e)))
+    })
+}
+
+fn read(path: &Path) -> ImageResult<History> {
+    let mut archive = ZipArchive::new(File::open(path)?).map_err(invalid)?;
+
+    let manifest: Manifest = {
+        let entry = archive.by_name(MANIFEST).map_err(invalid)?;
+        serde_json::from_reader(entry).map_err(invalid)?
+    };
+
+    if manifest.version != VERSION {
+        return Err(invalid(format!(
+            "unsupported project version {}",
+            manifest.version
+        )));
+    }
+
+    let mut source = Vec::new();
+    archive
+        .by_name(SOURCE)
+        .map_err(invalid)?
+        .read_to_end(&mut source)?;
+
+    let source = image::load_from_memory(&source)?.to_rgba8();
+
+    let (width, height) = source.dimensions();
+    validate(&manifest.edits, Rect::new(0, 0, width, height))?;
+
+    Ok(History::from_edits(source, manifest.edits))
+}
+
+/// Rectangles made in the editor never reach outside of the source image, a project that was
+/// edited by hand is rejected instead of cropping to nothing.
+fn validate(edits: &[Edit], source: Rect) -> ImageResult<()> {
+    for (i, edit) in edits.iter().enumerate() {
+        let rect = match edit {
+            Edit::Crop(rect) | Edit::Redact(rect, _) | Edit::Clip(Clip::Ellipse(rect)) => rect,
+            _ => continue,
+        };
+
+        if rect.is_empty() || rect.intersect(&source) != *rect {
+            return Err(invalid(format!(
+                "edit {} does not fit into the {}x{} source image: {}",
+                i + 1,
+                source.width,
+                source.height,
+                rect
+            )));
+        }
+    }
+
+    Ok(())
+}
+
+/// Writes the source image and every edit of `history` to `out`, undone edits are dropped.
+pub fn save<W: Write>(history: &History, mut out: W) -> ImageResult<()> {
+    let manifest = Manifest {
+        version: VERSION,
+        edits: history.edits().to_vec(),
+    };
+
+    let mut source = Vec::new();
+    DynamicImage::ImageRgba8(history.source().clone())
+        .write_to(&mut source, ImageOutputFormat::Png)?;
+
+    let mut archive = ZipWriter::new(Cursor::new(Vec::new()));
+
+    // the png is compressed already
+    archive
+        .start_file(
+            SOURCE,
+            FileOptions::default().compression_method(CompressionMethod::Stored),
+        )
+        .map_err(invalid)?;
+    archive.write_all(&source)?;
+
+    archive
+        .start_file(MANIFEST, FileOptions::default())
+        .map_err(invalid)?;
+    serde_json::to_writer_pretty(&mut archive, &manifest).map_err(invalid)?;
+
+    let archive = archive.finish().map_err(invalid)?;
+    out.write_all(&archive.into_inner())?;
+
+    Ok(())
+}
+
+fn invalid<E: fmt::Display>(e: E) -> ImageError {
+    ImageError::IoError(io::Error::new(io::ErrorKind::InvalidData, e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{Rgba, RgbaImage};
+
+    use super::*;
+    use crate::redact::Redaction;
+
+    fn round_trip(history: &History, name: &str) -> ImageResult<History> {
+        let path = std::env::temp_dir().join(format!("coral-test-{}.{}", name, EXTENSION));
+        save(history, File::create(&path)?)?;
+
+        let opened = open(&path);
+        std::fs::remove_file(&path)?;
+
+        opened
+    }
+
+    fn source() -> RgbaImage {
+        RgbaImage::from_fn(40, 30, |x, y| Rgba([x as u8 * 6, y as u8 * 8, 200, 255]))
+    }
+
+    #[test]
+    fn keeps_edits() {
+        let mut history = History::new(source());
+        history.push(Edit::Crop(Rect::new(2, 3, 30, 20)));
+        history.push(Edit::Redact(Rect::new(5, 5, 10, 10), Redaction::Pixelate));
+
+        let opened = round_trip(&history, "edits").unwrap();
+
+        assert_eq!(opened.edits(), history.edits());
+        assert_eq!(opened.source(), history.source());
+    }
+
+    #[test]
+    fn bakes_redactions() {
+        let mut history = History::new(source());
+        history.push(Edit::Redact(Rect::new(5, 5, 10, 10), Redaction::Fill));
+
+        let opened = round_trip(&history.bake_redactions(), "baked").unwrap();
+
+        assert_eq!(*opened.source().get_pixel(8, 8), Rgba([0, 0, 0, 255]));
+        assert_eq!(opened.flatten(), history.flatten());
+    }
+
+    #[test]
+    fn rejects_rects_outside_of_the_source() {
+        for rect in [
+            Rect::new(u32::MAX, u32::MAX, u32::MAX, u32::MAX),
+            Rect::new(30, 0, 20, 10),
+            Rect::new(0, 0, 0, 10),
+        ] {
+            let history = History::from_edits(source(), vec![Edit::Crop(rect)]);
+            let error = round_trip(&history, "invalid").err().unwrap().to_string();
+
+            assert!(error.contains("edit 1 does not fit into the 40x30 source image"));
+            assert!(error.contains("coral-test-invalid.coral"));
+        }
+    }
+}
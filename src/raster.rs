@@ -0,0 +1,155 @@
+//! Minimal anti-aliased software rasterizer, used to burn annotations into the saved image.
+//!
+//! Shapes are described by a coverage function mapping a pixel center to a value in `0..=1`,
+//! which keeps overlapping parts of a single shape from being blended twice.
+
+use image::{Pixel, Rgba, RgbaImage};
+
+pub type Point = [f64; 2];
+
+/// How a colour is combined with the pixels below it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    /// Regular alpha blending.
+    Over,
+    /// Darkens like a marker on paper, dark content stays visible.
+    Multiply,
+}
+
+fn sub(a: Point, b: Point) -> Point {
+    [a[0] - b[0], a[1] - b[1]]
+}
+
+fn dot(a: Point, b: Point) -> f64 {
+    a[0] * b[0] + a[1] * b[1]
+}
+
+fn cross(a: Point, b: Point) -> f64 {
+    a[0] * b[1] - a[1] * b[0]
+}
+
+pub fn distance(a: Point, b: Point) -> f64 {
+    let d = sub(a, b);
+    dot(d, d).sqrt()
+}
+
+/// Distance from `p` to the line segment between `a` and `b`.
+pub fn segment_distance(p: Point, a: Point, b: Point) -> f64 {
+    let ab = sub(b, a);
+    let len = dot(ab, ab);
+
+    let t = if len > 0.0 {
+        (dot(sub(p, a), ab) / len).clamp(0.0, 1.0)
+    } else {
+        0.0
+    };
+
+    distance(p, [a[0] + ab[0] * t, a[1] + ab[1] * t])
+}
+
+/// Signed distance from `p` to the outline of the convex polygon `points`, negative inside.
+pub fn polygon_distance(p: Point, points: &[Point]) -> f64 {
+    let edges = || points.iter().zip(points.iter().cycle().skip(1));
+
+    let outline = edges()
+        .map(|(a, b)| segment_distance(p, *a, *b))
+        .fold(f64::INFINITY, f64::min);
+
+    // inside if `p` is on the same side of every edge
+    let sides = edges().map(|(a, b)| cross(sub(*b, *a), sub(p, *a)));
+    let inside = sides.clone().all(|s| s >= 0.0) || sides.clone().all(|s| s <= 0.0);
+
+    if inside {
+        -outline
+    } else {
+        outline
+    }
+}
+
+/// Area enclosed by the closed polygon `points`, parts that overlap themselves cancel out.
+pub fn polygon_area(points: &[Point]) -> f64 {
+    let edges = points.iter().zip(points.iter().cycle().skip(1));
+
+    edges.map(|(a, b)| cross(*a, *b)).sum::<f64>().abs() / 2.0
+}
+
+/// Coverage of a stroke with the given `width` at `distance` from its center line.
+pub fn stroke(distance: f64, width: f64) -> f64 {
+    (width / 2.0 + 0.5 - distance).clamp(0.0, 1.0)
+}
+
+/// Coverage of a filled area at the signed `distance` from its outline.
+pub fn fill(distance: f64) -> f64 {
+    (0.5 - distance).clamp(0.0, 1.0)
+}
+
+/// Bounding box `[min, max]` of `points`, grown by `margin` in every direction.
+pub fn bounds(points: &[Point], margin: f64) -> [Point; 2] {
+    points.iter().fold(
+        [[f64::INFINITY; 2], [f64::NEG_INFINITY; 2]],
+        |[min, max], p| {
+            [
+                [min[0].min(p[0] - margin), min[1].min(p[1] - margin)],
+                [max[0].max(p[0] + margin), max[1].max(p[1] + margin)],
+            ]
+        },
+    )
+}
+
+/// Blends `color` with every pixel inside `bounds`, weighted by `coverage`.
+pub fn paint<F>(
+    image: &mut RgbaImage,
+    [min, max]: [Point; 2],
+    color: Rgba<u8>,
+    mode: Mode,
+    coverage: F,
+) where
+    F: Fn(Point) -> f64,
+{
+    let blend = match mode {
+        Mode::Over => blend,
+        Mode::Multiply => multiply,
+    };
+
+    let (width, height) = image.dimensions();
+
+    let clamp = |v: f64, max: u32| (v.max(0.0) as u32).min(max);
+    let (x0, y0) = (clamp(min[0].floor(), width), clamp(min[1].floor(), height));
+    let (x1, y1) = (clamp(max[0].ceil(), width), clamp(max[1].ceil(), height));
+
+    for y in y0..y1 {
+        for x in x0..x1 {
+            blend(
+                image,
+                x,
+                y,
+                color,
+                coverage([x as f64 + 0.5, y as f64 + 0.5]),
+            );
+        }
+    }
+}
+
+/// Multiplies a single pixel with `color`, weighted by its alpha and `coverage`; pixels outside
+/// the image are ignored.
+pub fn multiply(image: &mut RgbaImage, x: u32, y: u32, color: Rgba<u8>, coverage: f64) {
+    if coverage > 0.0 && x < image.width() && y < image.height() {
+        let strength = color[3] as f64 / 255.0 * coverage.min(1.0);
+        let pixel = image.get_pixel_mut(x, y);
+
+        for (channel, c) in pixel.0.iter_mut().zip(color.0.iter()).take(3) {
+            let factor = 1.0 - strength * (1.0 - *c as f64 / 255.0);
+            *channel = (*channel as f64 * factor).round() as u8;
+        }
+    }
+}
+
+/// Blends `color` over a single pixel, weighted by `coverage`; pixels outside the image are
+/// ignored.
+pub fn blend(image: &mut RgbaImage, x: u32, y: u32, mut color: Rgba<u8>, coverage: f64) {
+    if coverage > 0.0 && x < image.width() && y < image.height() {
+        color[3] = (color[3] as f64 * coverage.min(1.0)).round() as u8;
+
+        image.get_pixel_mut(x, y).blend(&color);
+    }
+}
@@ -0,0 +1,80 @@
+use std::str::FromStr;
+
+use image::{imageops, Rgba, RgbaImage};
+use serde::{Deserialize, Serialize};
+
+use crate::history::Rect;
+
+/// How the content of a region is hidden.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Redaction {
+    /// Opaque black box, the only method that can not be undone by image processing.
+    #[default]
+    Fill,
+    Pixelate,
+    Blur,
+}
+
+impl Redaction {
+    pub fn next(self) -> Self {
+        match self {
+            Redaction::Fill => Redaction::Pixelate,
+            Redaction::Pixelate => Redaction::Blur,
+            Redaction::Blur => Redaction::Fill,
+        }
+    }
+
+    pub fn apply(self, image: &mut RgbaImage, rect: Rect) {
+        let (width, height) = image.dimensions();
+        let rect = rect.intersect(&Rect::new(0, 0, width, height));
+
+        if rect.is_empty() {
+            return;
+        }
+
+        // the effect has to scale with the region, otherwise large text stays readable
+        let strength = u32::max(8, u32::max(rect.width, rect.height) / 16);
+
+        match self {
+            Redaction::Fill => {
+                let black = RgbaImage::from_pixel(rect.width, rect.height, Rgba([0, 0, 0, 0xff]));
+                imageops::replace(image, &black, rect.x, rect.y);
+            }
+            Redaction::Pixelate => {
+                let region = imageops::crop_imm(image, rect.x, rect.y, rect.width, rect.height);
+                let (w, h) = (
+                    u32::max(1, rect.width / strength),
+                    u32::max(1, rect.height / strength),
+                );
+
+                let small = imageops::resize(&region.to_image(), w, h, imageops::Triangle);
+                let blocks = imageops::resize(&small, rect.width, rect.height, imageops::Nearest);
+
+                imageops::replace(image, &blocks, rect.x, rect.y);
+            }
+            Redaction::Blur => {
+                let region = imageops::crop_imm(image, rect.x, rect.y, rect.width, rect.height);
+                let blurred = imageops::blur(&region.to_image(), strength as f32);
+
+                imageops::replace(image, &blurred, rect.x, rect.y);
+            }
+        }
+    }
+}
+
+impl FromStr for Redaction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "fill" => Ok(Redaction::Fill),
+            "pixelate" => Ok(Redaction::Pixelate),
+            "blur" => Ok(Redaction::Blur),
+            _ => Err(format!(
+                "redaction has to be fill, pixelate or blur, got `{}`",
+                s
+            )),
+        }
+    }
+}
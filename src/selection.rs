@@ -0,0 +1,342 @@
+use std::str::FromStr;
+
+use crate::raster::Point;
+
+/// Which corner of a [`Selection`] a coordinate belongs to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Corner {
+    From,
+    To,
+}
+
+impl Corner {
+    fn index(self) -> usize {
+        match self {
+            Corner::From => 0,
+            Corner::To => 1,
+        }
+    }
+}
+
+/// Grabbed edges of a selection; a corner handle moves one vertical and one horizontal edge.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Handle {
+    pub x: Option<Corner>,
+    pub y: Option<Corner>,
+}
+
+impl Handle {
+    /// The corner that follows the mouse while a new selection is dragged open.
+    pub const NEW: Handle = Handle {
+        x: Some(Corner::To),
+        y: Some(Corner::To),
+    };
+
+    /// Every corner and edge, in the order they are cycled through with the keyboard.
+    pub const ALL: [Handle; 8] = [
+        Handle::NEW,
+        Handle::new(Some(Corner::From), Some(Corner::From)),
+        Handle::new(Some(Corner::To), Some(Corner::From)),
+        Handle::new(Some(Corner::From), Some(Corner::To)),
+        Handle::new(Some(Corner::To), None),
+        Handle::new(Some(Corner::From), None),
+        Handle::new(None, Some(Corner::To)),
+        Handle::new(None, Some(Corner::From)),
+    ];
+
+    const fn new(x: Option<Corner>, y: Option<Corner>) -> Self {
+        Self { x, y }
+    }
+}
+
+/// Restricts the shape of a selection while it is resized.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Constraint {
+    /// Width divided by height.
+    Ratio(f64),
+    Size(u32, u32),
+}
+
+impl FromStr for Constraint {
+    type Err = String;
+
+    /// `W:H` for a ratio, `WxH` for a size.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let sides = |(w, h): (&str, &str)| match (w.parse::<u32>(), h.parse::<u32>()) {
+            (Ok(w), Ok(h)) if w > 0 && h > 0 => Some((w, h)),
+            _ => None,
+        };
+
+        let constraint = if let Some(ratio) = s.split_once(':') {
+            sides(ratio).map(|(w, h)| Constraint::Ratio(w as f64 / h as f64))
+        } else {
+            s.split_once('x')
+                .and_then(sides)
+                .map(|(w, h)| Constraint::Size(w, h))
+        };
+
+        constraint.ok_or_else(|| {
+            format!(
+                "expected a ratio like `16:9` or a size like `1280x720`, got `{}`",
+                s
+            )
+        })
+    }
+}
+
+/// A rectangular selection in image coordinates that can still be moved and resized.
+///
+/// The corners are not ordered, so dragging an edge past the opposite one just flips the
+/// rectangle.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Selection {
+    pub corners: [Point; 2],
+}
+
+impl Selection {
+    pub fn new(at: Point) -> Self {
+        Self { corners: [at, at] }
+    }
+
+    pub fn from(&self) -> Point {
+        self.corners[0]
+    }
+
+    pub fn to(&self) -> Point {
+        self.corners[1]
+    }
+
+    pub fn is_empty(&self) -> bool {
+        let [a, b] = self.corners;
+        a[0] == b[0] || a[1] == b[1]
+    }
+
+    pub fn contains(&self, p: Point) -> bool {
+        let [a, b] = self.corners;
+        let between = |v: f64, a: f64, b: f64| f64::min(a, b) <= v && v <= f64::max(a, b);
+
+        between(p[0], a[0], b[0]) && between(p[1], a[1], b[1])
+    }
+
+    /// The handle within `tolerance` of `p`, corners take precedence over edges.
+    pub fn handle_at(&self, p: Point, tolerance: f64) -> Option<Handle> {
+        let [a, b] = self.corners;
+        let near = |v: f64, edge: f64| (v - edge).abs() <= tolerance;
+        let inside = |v: f64, a: f64, b: f64| {
+            f64::min(a, b) - tolerance <= v && v <= f64::max(a, b) + tolerance
+        };
+
+        let corner = |v: f64, a: f64, b: f64| {
+            if near(v, b) {
+                Some(Corner::To)
+            } else if near(v, a) {
+                Some(Corner::From)
+            } else {
+                None
+            }
+        };
+
+        if !inside(p[0], a[0], b[0]) || !inside(p[1], a[1], b[1]) {
+            return None;
+        }
+
+        match (corner(p[0], a[0], b[0]), corner(p[1], a[1], b[1])) {
+            (None, None) => None,
+            (x, y) => Some(Handle { x, y }),
+        }
+    }
+
+    /// Moves the edges of `handle` to `p`.
+    pub fn resize(&mut self, handle: Handle, p: Point) {
+        if let Some(corner) = handle.x {
+            self.corners[corner.index()][0] = p[0];
+        }
+
+        if let Some(corner) = handle.y {
+            self.corners[corner.index()][1] = p[1];
+        }
+    }
+
+    /// Moves the edges of `handle`, or the whole selection if it is `None`, by `delta` after
+    /// snapping them to whole pixels.
+    pub fn nudge(&mut self, handle: Option<Handle>, delta: Point) {
+        for corner in &mut self.corners {
+            *corner = [corner[0].round(), corner[1].round()];
+        }
+
+        match handle {
+            Some(handle) => {
+                if let Some(corner) = handle.x {
+                    self.corners[corner.index()][0] += delta[0];
+                }
+
+                if let Some(corner) = handle.y {
+                    self.corners[corner.index()][1] += delta[1];
+                }
+            }
+            None => self.translate(delta),
+        }
+    }
+
+    /// Enforces `constraint` after `handle` was moved, the opposite edges stay in place. If only
+    /// one edge was moved, the other axis grows or shrinks around its center.
+    ///
+    /// The selection is kept inside an image of `bounds` pixels: a ratio shrinks until it fits,
+    /// a fixed size is shifted back in.
+    pub fn constrain(&mut self, handle: Handle, constraint: Constraint, bounds: Point) {
+        let [a, b] = self.corners;
+        let (width, height) = ((b[0] - a[0]).abs(), (b[1] - a[1]).abs());
+
+        let mut size = match (constraint, handle.x, handle.y) {
+            (Constraint::Size(width, height), _, _) => [width as f64, height as f64],
+            (Constraint::Ratio(ratio), Some(_), Some(_)) => {
+                // the larger side follows the mouse
+                if width < height * ratio {
+                    [height * ratio, height]
+                } else {
+                    [width, width / ratio]
+                }
+            }
+            (Constraint::Ratio(ratio), Some(_), None) => [width, width / ratio],
+            (Constraint::Ratio(ratio), None, _) => [height * ratio, height],
+        };
+
+        if let Constraint::Ratio(_) = constraint {
+            // room between the edges that stay in place and the border of the image
+            let room = |axis: usize, corner: Option<Corner>| {
+                let [from, to] = [self.corners[0][axis], self.corners[1][axis]];
+                let grows = if to < from { -1.0 } else { 1.0 };
+
+                let room = match corner {
+                    Some(Corner::From) if grows > 0.0 => to,
+                    Some(Corner::From) => bounds[axis] - to,
+                    Some(Corner::To) if grows > 0.0 => bounds[axis] - from,
+                    Some(Corner::To) => from,
+                    None => {
+                        let center = (from + to) / 2.0;
+                        2.0 * f64::min(center, bounds[axis] - center)
+                    }
+                };
+
+                f64::max(0.0, room)
+            };
+
+            let scale = [room(0, handle.x) / size[0], room(1, handle.y) / size[1]]
+                .iter()
+                .filter(|scale| scale.is_finite())
+                .fold(1.0, |acc: f64, scale| acc.min(*scale));
+
+            size = [size[0] * scale, size[1] * scale];
+        }
+
+        for (axis, corner) in [handle.x, handle.y].iter().enumerate() {
+            let [from, to] = [self.corners[0][axis], self.corners[1][axis]];
+            let sign = if to < from { -1.0 } else { 1.0 };
+
+            match corner {
+                Some(Corner::From) => self.corners[0][axis] = to - sign * size[axis],
+                Some(Corner::To) => self.corners[1][axis] = from + sign * size[axis],
+                None => {
+                    let center = (from + to) / 2.0;
+                    self.corners[0][axis] = center - sign * size[axis] / 2.0;
+                    self.corners[1][axis] = center + sign * size[axis] / 2.0;
+                }
+            }
+        }
+
+        if let Constraint::Size(..) = constraint {
+            self.shift_inside(bounds);
+        }
+    }
+
+    /// Moves the selection back into an image of `bounds` pixels, without resizing it. A
+    /// selection larger than the image sticks to the top left corner.
+    pub fn shift_inside(&mut self, bounds: Point) {
+        let [a, b] = self.corners;
+        let mut delta = [0.0, 0.0];
+
+        for axis in 0..2 {
+            let (low, high) = (f64::min(a[axis], b[axis]), f64::max(a[axis], b[axis]));
+
+            if high > bounds[axis] {
+                delta[axis] = bounds[axis] - high;
+            }
+            if low + delta[axis] < 0.0 {
+                delta[axis] = -low;
+            }
+        }
+
+        self.translate(delta);
+    }
+
+    pub fn translate(&mut self, [dx, dy]: Point) {
+        for corner in &mut self.corners {
+            corner[0] += dx;
+            corner[1] += dy;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BOUNDS: Point = [100.0, 50.0];
+
+    fn rect(selection: &Selection) -> [f64; 4] {
+        let [a, b] = selection.corners;
+        [
+            f64::min(a[0], b[0]),
+            f64::min(a[1], b[1]),
+            (b[0] - a[0]).abs(),
+            (b[1] - a[1]).abs(),
+        ]
+    }
+
+    #[test]
+    fn ratio_shrinks_to_fit() {
+        let mut selection = Selection {
+            corners: [[60.0, 10.0], [140.0, 20.0]],
+        };
+        selection.constrain(Handle::NEW, Constraint::Ratio(2.0), BOUNDS);
+
+        // 40 pixels are left to the right edge, 40 below
+        assert_eq!(rect(&selection), [60.0, 10.0, 40.0, 20.0]);
+
+        let mut selection = Selection {
+            corners: [[50.0, 40.0], [10.0, 30.0]],
+        };
+        selection.constrain(Handle::NEW, Constraint::Ratio(1.0), BOUNDS);
+
+        // dragged to the top left, 40 pixels are left above
+        assert_eq!(rect(&selection), [10.0, 0.0, 40.0, 40.0]);
+    }
+
+    #[test]
+    fn ratio_on_an_edge_shrinks_around_the_center() {
+        let mut selection = Selection {
+            corners: [[10.0, 20.0], [90.0, 30.0]],
+        };
+        selection.constrain(
+            Handle::new(Some(Corner::To), None),
+            Constraint::Ratio(1.0),
+            BOUNDS,
+        );
+
+        // the center at 25 leaves 25 pixels above it
+        assert_eq!(rect(&selection), [10.0, 0.0, 50.0, 50.0]);
+    }
+
+    #[test]
+    fn size_is_shifted_inside() {
+        let mut selection = Selection::new([90.0, 45.0]);
+        selection.constrain(Handle::NEW, Constraint::Size(30, 20), BOUNDS);
+
+        assert_eq!(rect(&selection), [70.0, 30.0, 30.0, 20.0]);
+
+        let mut selection = Selection::new([-5.0, 10.0]);
+        selection.constrain(Handle::NEW, Constraint::Size(200, 20), BOUNDS);
+
+        assert_eq!(rect(&selection), [0.0, 10.0, 200.0, 20.0]);
+    }
+}
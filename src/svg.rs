@@ -0,0 +1,298 @@
+//! SVG export: the image is embedded as a PNG and the annotations stay vector shapes, so they
+//! can still be restyled after saving.
+
+use std::fmt::{self, Write};
+
+use image::Rgba;
+
+use crate::{
+    annotation::{Annotation, Outline, Shape},
+    clip::Clip,
+    font,
+    history::Rect,
+    raster::{Mode, Point},
+};
+
+/// An SVG showing the area `crop` of the source image, `png` holds the pixels of that area.
+/// Annotations and clips are in source image coordinates.
+pub fn document(crop: Rect, png: &[u8], annotations: &[Annotation], clips: &[Clip]) -> String {
+    let mut svg = String::new();
+
+    write_document(&mut svg, crop, png, annotations, clips)
+        .expect("writing to a string never fails");
+
+    svg
+}
+
+fn write_document(
+    svg: &mut String,
+    crop: Rect,
+    png: &[u8],
+    annotations: &[Annotation],
+    clips: &[Clip],
+) -> fmt::Result {
+    let Rect {
+        x,
+        y,
+        width,
+        height,
+    } = crop;
+
+    writeln!(svg, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(
+        svg,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" xmlns:xlink="http://www.w3.org/1999/xlink" width="{w}" height="{h}" viewBox="0 0 {w} {h}">"#,
+        w = width,
+        h = height
+    )?;
+
+    if !clips.is_empty() {
+        writeln!(svg, "<defs>")?;
+        for (i, clip) in clips.iter().enumerate() {
+            writeln!(
+                svg,
+                r#"<clipPath id="clip-{}"><polygon points="{}"/></clipPath>"#,
+                i,
+                points(&clip.outline())
+            )?;
+        }
+        writeln!(svg, "</defs>")?;
+    }
+
+    writeln!(
+        svg,
+        r#"<image width="{}" height="{}" xlink:href="data:image/png;base64,{}"/>"#,
+        width,
+        height,
+        base64::encode(png)
+    )?;
+
+    // everything below is placed in source image coordinates
+    if x == 0 && y == 0 {
+        writeln!(svg, "<g>")?;
+    } else {
+        writeln!(svg, r#"<g transform="translate(-{} -{})">"#, x, y)?;
+    }
+
+    // annotations must not reach outside of the clip shapes, like in the saved image
+    for i in 0..clips.len() {
+        writeln!(svg, r#"<g clip-path="url(#clip-{})">"#, i)?;
+    }
+
+    for annotation in annotations {
+        write_annotation(svg, annotation)?;
+    }
+
+    for _ in 0..clips.len() + 1 {
+        writeln!(svg, "</g>")?;
+    }
+
+    writeln!(svg, "</svg>")
+}
+
+fn write_annotation(svg: &mut String, annotation: &Annotation) -> fmt::Result {
+    let color = annotation.color();
+    let blend = match annotation.mode() {
+        Mode::Multiply => r#" style="mix-blend-mode:multiply""#,
+        Mode::Over => "",
+    };
+
+    let stroke = format!(
+        r#"fill="none" stroke="{}" stroke-opacity="{}" stroke-width="{}" stroke-linecap="round" stroke-linejoin="round"{}"#,
+        rgb(color),
+        opacity(color),
+        round(annotation.width()),
+        blend
+    );
+    let fill = format!(
+        r#"fill="{}" fill-opacity="{}"{}"#,
+        rgb(color),
+        opacity(color),
+        blend
+    );
+
+    match &annotation.shape {
+        Shape::Rectangle { from, to } => writeln!(
+            svg,
+            r#"<rect x="{}" y="{}" width="{}" height="{}" {}/>"#,
+            round(f64::min(from[0], to[0])),
+            round(f64::min(from[1], to[1])),
+            round((to[0] - from[0]).abs()),
+            round((to[1] - from[1]).abs()),
+            stroke
+        )?,
+        Shape::Marker { at, .. } => writeln!(
+            svg,
+            r#"<circle cx="{}" cy="{}" r="{}" {}/>"#,
+            round(at[0]),
+            round(at[1]),
+            round(annotation.marker_radius()),
+            fill
+        )?,
+        Shape::Freehand { .. } | Shape::Highlight { .. } => {
+            let Outline { lines, .. } = annotation.outline();
+
+            // the outline is a chain of segments, each one starting where the last one ended
+            let chain = lines
+                .first()
+                .map(|line| line[0])
+                .into_iter()
+                .chain(lines.iter().map(|line| line[1]))
+                .collect::<Vec<_>>();
+
+            writeln!(svg, r#"<polyline points="{}" {}/>"#, points(&chain), stroke)?
+        }
+        _ => {
+            let Outline { lines, polygons } = annotation.outline();
+
+            for [a, b] in lines {
+                writeln!(
+                    svg,
+                    r#"<line x1="{}" y1="{}" x2="{}" y2="{}" {}/>"#,
+                    round(a[0]),
+                    round(a[1]),
+                    round(b[0]),
+                    round(b[1]),
+                    stroke
+                )?;
+            }
+
+            for polygon in polygons {
+                writeln!(svg, r#"<polygon points="{}" {}/>"#, points(&polygon), fill)?;
+            }
+        }
+    }
+
+    if let Some((at, text)) = annotation.text() {
+        let color = annotation.text_color();
+
+        writeln!(
+            svg,
+            r#"<text x="{}" y="{}" font-family="DejaVu Sans" font-size="{}" fill="{}" fill-opacity="{}">{}</text>"#,
+            round(at[0]),
+            round(at[1]),
+            round(font::em_size(annotation.style.font_size)),
+            rgb(color),
+            opacity(color),
+            escape(&text)
+        )?;
+    }
+
+    Ok(())
+}
+
+fn points(points: &[Point]) -> String {
+    points
+        .iter()
+        .map(|p| format!("{},{}", round(p[0]), round(p[1])))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Hundredths of a pixel are plenty and keep long outlines short.
+fn round(v: f64) -> f64 {
+    // adding zero turns `-0` into `0`
+    (v * 100.0).round() / 100.0 + 0.0
+}
+
+fn rgb(Rgba([r, g, b, _]): Rgba<u8>) -> String {
+    format!("#{:02X}{:02X}{:02X}", r, g, b)
+}
+
+fn opacity(color: Rgba<u8>) -> f64 {
+    color[3] as f64 / 255.0
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::annotation::Style;
+
+    fn source() -> Rect {
+        Rect::new(0, 0, 60, 40)
+    }
+
+    fn annotation(shape: Shape) -> Annotation {
+        Annotation::new(shape, Style::default())
+    }
+
+    #[test]
+    fn annotations_are_nested_in_every_clip() {
+        let clips = [
+            Clip::Ellipse(Rect::new(0, 0, 60, 40)),
+            Clip::Lasso(vec![[0.0, 0.0], [30.0, 0.0], [0.0, 30.0]]),
+        ];
+        let svg = document(Rect::new(5, 7, 30, 20), &[], &[], &clips);
+
+        assert!(svg.contains(r#"<clipPath id="clip-0">"#));
+        assert!(svg.contains(r#"<clipPath id="clip-1"><polygon points="0,0 30,0 0,30"/>"#));
+
+        let groups = svg.find(r#"<g transform="translate(-5 -7)">"#).unwrap();
+        let outer = svg.find(r#"<g clip-path="url(#clip-0)">"#).unwrap();
+        let inner = svg.find(r#"<g clip-path="url(#clip-1)">"#).unwrap();
+
+        assert!(groups < outer && outer < inner);
+        assert_eq!(svg.matches("<g").count(), svg.matches("</g>").count());
+    }
+
+    #[test]
+    fn uncropped_images_are_not_translated() {
+        let svg = document(source(), &[], &[], &[]);
+
+        assert!(!svg.contains("translate"));
+        assert!(!svg.contains("-0"));
+    }
+
+    #[test]
+    fn escapes_text() {
+        assert_eq!(escape("a < b && c > d"), "a &lt; b &amp;&amp; c &gt; d");
+
+        let text = annotation(Shape::Text {
+            at: [10.0, 20.0],
+            text: "<b>".to_string(),
+        });
+        let svg = document(source(), &[], &[text], &[]);
+
+        assert!(svg.contains(">&lt;b&gt;</text>"));
+    }
+
+    #[test]
+    fn arrows_have_a_filled_head() {
+        let arrow = annotation(Shape::Arrow {
+            from: [10.0, 10.0],
+            to: [50.0, 10.0],
+        });
+        let svg = document(source(), &[], &[arrow], &[]);
+
+        assert!(svg.contains(r#"<line x1="10" y1="10" x2="#));
+        assert_eq!(svg.matches("<polygon ").count(), 1);
+        assert!(svg.contains(r#"<polygon points="50,10 "#));
+    }
+
+    #[test]
+    fn markers_are_numbered_circles_with_rounded_coordinates() {
+        let marker = annotation(Shape::Marker {
+            at: [10.123_456, 20.0],
+            number: 3,
+        });
+        let svg = document(source(), &[], &[marker], &[]);
+
+        assert!(svg.contains(r#"<circle cx="10.12" cy="20" r=""#));
+        assert!(svg.contains(">3</text>"));
+
+        let decimals = |v: &str| v.split_once('.').map_or(0, |(_, d)| d.len());
+        for attribute in svg.split(' ').filter_map(|a| a.split_once("=\"")) {
+            let value = attribute.1.split('"').next().unwrap();
+
+            if value.parse::<f64>().is_ok() {
+                assert!(decimals(value) <= 2, "{}", value);
+            }
+        }
+    }
+}
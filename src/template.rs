@@ -0,0 +1,213 @@
+use std::{
+    fmt::Write,
+    fs::{File, OpenOptions},
+    io,
+    path::{Path, PathBuf, MAIN_SEPARATOR},
+};
+
+use chrono::{DateTime, TimeZone};
+
+/// File name used when the output only names a directory.
+const DEFAULT_NAME: &str = "coral_%Y-%m-%d_%H%M%S_{n}.png";
+
+/// Output path with placeholders that are filled in when saving:
+///
+/// - `strftime` fields like `%Y-%m-%d`,
+/// - `{w}` and `{h}` for the image dimensions,
+/// - `{n}`, a counter that is increased until the path does not exist yet.
+///
+/// A leading `~` is replaced with the home directory.
+#[derive(Debug, Clone)]
+pub struct Template(String);
+
+impl Template {
+    /// Relative templates are placed in `default_dir`; if `output` is a directory, a default file
+    /// name is used.
+    pub fn new(output: &str, default_dir: Option<&Path>) -> Self {
+        let output = expand_home(output);
+
+        // joined first, a relative directory has to be looked up in `default_dir`
+        let path = match default_dir {
+            Some(dir) if Path::new(&output).is_relative() => {
+                Path::new(&expand_home(&dir.to_string_lossy())).join(&output)
+            }
+            _ => PathBuf::from(&output),
+        };
+
+        let path = if output.ends_with(MAIN_SEPARATOR) || path.is_dir() {
+            path.join(DEFAULT_NAME)
+        } else {
+            path
+        };
+
+        Self(path.to_string_lossy().into_owned())
+    }
+
+    /// A template for a file with the default name in `dir`.
+    pub fn in_dir(dir: &Path) -> Self {
+        Self::new(
+            &format!("{}{}", dir.to_string_lossy(), MAIN_SEPARATOR),
+            None,
+        )
+    }
+
+    /// The unexpanded template, only useful to look at the extension.
+    pub fn path(&self) -> &Path {
+        Path::new(&self.0)
+    }
+
+    /// A template that gives every saved file a new name, a `{n}` counter is added in front of
+    /// the extension if there is none yet.
+    pub fn numbered(&self) -> Self {
+        if self.0.contains("{n}") {
+            return self.clone();
+        }
+
+        let path = self.path();
+        let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+        let name = match path.extension() {
+            Some(extension) => format!("{}_{{n}}.{}", stem, extension.to_string_lossy()),
+            None => format!("{}_{{n}}", stem),
+        };
+
+        Self(path.with_file_name(name).to_string_lossy().into_owned())
+    }
+
+    /// Fills in the placeholders and creates the file. With a `{n}` counter the first name that
+    /// can be created without replacing a file is taken, which is safe against other processes
+    /// saving at the same time; without one an existing file is replaced.
+    pub fn create<Tz>(
+        &self,
+        now: &DateTime<Tz>,
+        dimensions: (u32, u32),
+    ) -> io::Result<(PathBuf, File)>
+    where
+        Tz: TimeZone,
+        Tz::Offset: std::fmt::Display,
+    {
+        let formatted = self
+            .format(now, dimensions)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+
+        if !formatted.contains("{n}") {
+            let path = PathBuf::from(formatted);
+            let file = File::create(&path)?;
+
+            return Ok((path, file));
+        }
+
+        for n in 1u64.. {
+            let path = PathBuf::from(formatted.replace("{n}", &n.to_string()));
+
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(file) => return Ok((path, file)),
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(e),
+            }
+        }
+
+        Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("no free file name for `{}`", self.0),
+        ))
+    }
+
+    /// The path with every placeholder except `{n}` filled in.
+    fn format<Tz>(&self, now: &DateTime<Tz>, (width, height): (u32, u32)) -> Result<String, String>
+    where
+        Tz: TimeZone,
+        Tz::Offset: std::fmt::Display,
+    {
+        let mut formatted = String::new();
+        write!(formatted, "{}", now.format(&self.0))
+            .map_err(|_| format!("invalid time format in `{}`", self.0))?;
+
+        Ok(formatted
+            .replace("{w}", &width.to_string())
+            .replace("{h}", &height.to_string()))
+    }
+}
+
+fn expand_home(path: &str) -> String {
+    match (path.strip_prefix('~'), std::env::var("HOME")) {
+        (Some(rest), Ok(home)) if rest.is_empty() || rest.starts_with(MAIN_SEPARATOR) => {
+            format!("{}{}", home, rest)
+        }
+        _ => path.to_owned(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Local, TimeZone};
+
+    use super::*;
+
+    /// An empty directory that is only used by one test.
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("coral-template-{}", name));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+
+        dir
+    }
+
+    #[test]
+    fn format_fills_in_placeholders() {
+        let now = Local.with_ymd_and_hms(2021, 3, 4, 5, 6, 7).unwrap();
+        let template = Template("shot_%Y-%m-%d_%H%M%S_{w}x{h}_{n}.png".to_owned());
+
+        assert_eq!(
+            template.format(&now, (640, 480)),
+            Ok("shot_2021-03-04_050607_640x480_{n}.png".to_owned())
+        );
+    }
+
+    #[test]
+    fn numbered_adds_a_counter() {
+        let numbered = |path: &str| Template(path.to_owned()).numbered().0;
+
+        assert_eq!(numbered("/tmp/out.png"), "/tmp/out_{n}.png");
+        assert_eq!(numbered("/tmp/out"), "/tmp/out_{n}");
+        assert_eq!(numbered("/tmp/a_{n}.png"), "/tmp/a_{n}.png");
+    }
+
+    #[test]
+    fn expands_home() {
+        let home = std::env::var("HOME").unwrap();
+
+        assert_eq!(expand_home("~"), home);
+        assert_eq!(expand_home("~/shots"), format!("{}/shots", home));
+        assert_eq!(expand_home("~user/shots"), "~user/shots");
+        assert_eq!(expand_home("shots/~"), "shots/~");
+    }
+
+    #[test]
+    fn directories_are_looked_up_in_the_default_dir() {
+        let dir = temp_dir("dirs");
+        std::fs::create_dir(dir.join("shots")).unwrap();
+
+        let template = Template::new("shots", Some(&dir));
+        assert_eq!(template.path(), dir.join("shots").join(DEFAULT_NAME));
+
+        let template = Template::new("shot.png", Some(&dir));
+        assert_eq!(template.path(), dir.join("shot.png"));
+    }
+
+    #[test]
+    fn counter_skips_existing_files() {
+        let dir = temp_dir("counter");
+        std::fs::write(dir.join("a_1.png"), b"").unwrap();
+        std::fs::write(dir.join("a_2.png"), b"").unwrap();
+
+        let template = Template::new("a_{n}.png", Some(&dir));
+        let now = Local::now();
+
+        let (first, _) = template.create(&now, (1, 1)).unwrap();
+        let (second, _) = template.create(&now, (1, 1)).unwrap();
+
+        assert_eq!(first, dir.join("a_3.png"));
+        assert_eq!(second, dir.join("a_4.png"));
+        assert_eq!(std::fs::read(dir.join("a_1.png")).unwrap(), b"");
+    }
+}